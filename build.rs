@@ -0,0 +1,26 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Records the git commit and build time for `GET /version`.
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built_at);
+
+    // Pick up new commits; outside a git checkout this reruns on every build
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,29 @@
+//! Runs the crawl + screenshot pipeline for one URL without the HTTP server.
+//!
+//! Requires a WebDriver (e.g. chromedriver) listening on the default port:
+//!
+//! ```sh
+//! cargo run --example analyze_url -- https://example.com
+//! ```
+
+use anyhow::Result;
+use screenshot_api::{analyze_url, ApiConfig, ScreenshotTaker};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "https://example.com".to_string());
+
+    let config = ApiConfig {
+        screenshot_dir: "screenshots".to_string(),
+        ..Default::default()
+    };
+    let screenshot_taker = ScreenshotTaker::new(config.screenshot_config()).await?;
+
+    let response = analyze_url(&url, &config, &screenshot_taker).await;
+    screenshot_taker.close().await?;
+
+    println!("{}", serde_json::to_string_pretty(&response?)?);
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use moka::sync::Cache;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, Semaphore};
+
+use super::{is_valid_request_id, RedirectBatchItem};
+use crate::url_crawler::Crawler;
+use crate::url_parser::{ParsedUrl, ParserConfig};
+
+/// Upper bound on the number of finished streamed batch jobs whose status is
+/// kept.
+pub(super) const MAX_TRACKED_BATCH_JOBS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchJobState {
+    Running,
+    Completed,
+    /// Writing the output file failed; see `error`
+    Failed,
+}
+
+/// Progress of a `POST /redirects` batch streamed to disk, served by
+/// `GET /redirects/jobs/{job_id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchJobStatus {
+    pub job_id: String,
+    pub state: BatchJobState,
+    pub total: usize,
+    /// URLs whose result has been written
+    pub completed: usize,
+    /// Written results that carry an `error`
+    pub failed: usize,
+    /// NDJSON file the results are written to, one line per URL
+    pub output_path: String,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// When the last result was written; unset while running
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Time spent so far, or in total once finished
+    pub elapsed_ms: u64,
+}
+
+impl BatchJobStatus {
+    /// A copy with `elapsed_ms` brought up to date.
+    fn snapshot(&self) -> Self {
+        let end = self.finished_at.unwrap_or_else(Utc::now);
+        Self {
+            elapsed_ms: (end - self.started_at).num_milliseconds().max(0) as u64,
+            ..self.clone()
+        }
+    }
+}
+
+type SharedStatus = Arc<Mutex<BatchJobStatus>>;
+
+/// Status of running and recent streamed batch jobs, shared by all handlers.
+/// Only finished jobs are ever dropped to make room.
+#[derive(Clone)]
+pub struct BatchJobs {
+    running: Arc<Mutex<HashMap<String, SharedStatus>>>,
+    finished: Cache<String, SharedStatus>,
+}
+
+impl Default for BatchJobs {
+    fn default() -> Self {
+        Self::with_capacity(MAX_TRACKED_BATCH_JOBS)
+    }
+}
+
+impl BatchJobs {
+    /// Keeps the status of up to `capacity` finished jobs.
+    pub(super) fn with_capacity(capacity: u64) -> Self {
+        Self {
+            running: Arc::default(),
+            finished: Cache::builder().max_capacity(capacity).build(),
+        }
+    }
+
+    pub fn status(&self, job_id: &str) -> Option<BatchJobStatus> {
+        let running = self.running().get(job_id).cloned();
+        let status = running.or_else(|| self.finished.get(job_id))?;
+        let status = status.lock().unwrap_or_else(|e| e.into_inner()).snapshot();
+        Some(status)
+    }
+
+    /// Up to `limit` tracked jobs, running or recently finished, newest first.
+    pub fn list(&self, limit: usize) -> Vec<BatchJobStatus> {
+        let running: Vec<SharedStatus> = self.running().values().cloned().collect();
+        let mut jobs: Vec<BatchJobStatus> = running.into_iter()
+            .chain(self.finished.iter().map(|(_, status)| status))
+            .map(|status| status.lock().unwrap_or_else(|e| e.into_inner()).snapshot())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.started_at));
+        jobs.truncate(limit);
+        jobs
+    }
+
+    /// Starts crawling `urls` in the background, writing each result to
+    /// `{output_dir}/{job_id}.ndjson` as soon as it completes, and returns the
+    /// job's initial status.
+    pub(super) async fn start(
+        &self,
+        urls: Vec<String>,
+        output_dir: &str,
+        parser: ParserConfig,
+        crawler: Crawler,
+        concurrency: usize,
+    ) -> std::io::Result<BatchJobStatus> {
+        let job_id = super::new_request_id();
+        let output_path = PathBuf::from(output_dir).join(format!("{}.ndjson", job_id));
+        let file = tokio::fs::File::create(&output_path).await?;
+        let status = BatchJobStatus {
+            job_id: job_id.clone(),
+            state: BatchJobState::Running,
+            total: urls.len(),
+            completed: 0,
+            failed: 0,
+            output_path: output_path.to_string_lossy().into_owned(),
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+            elapsed_ms: 0,
+        };
+        let shared = Arc::new(Mutex::new(status.clone()));
+        self.running().insert(job_id.clone(), shared.clone());
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            stream_batch(urls, file, parser, crawler, concurrency, shared.clone()).await;
+            // Listed as finished before it stops being listed as running
+            jobs.finished.insert(job_id.clone(), shared);
+            jobs.running().remove(&job_id);
+        });
+        Ok(status)
+    }
+
+    fn running(&self) -> std::sync::MutexGuard<'_, HashMap<String, SharedStatus>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Crawls `urls` at most `concurrency` at a time and appends each result to
+/// `file`. Results travel through a bounded channel and a crawl keeps its
+/// slot until its result is queued, so crawls can't outrun disk writes and
+/// memory stays bounded however long the batch is. Lines are written in
+/// completion order; `position` gives each one's place in the input.
+async fn stream_batch(
+    urls: Vec<String>,
+    file: tokio::fs::File,
+    parser: ParserConfig,
+    crawler: Crawler,
+    concurrency: usize,
+    status: Arc<Mutex<BatchJobStatus>>,
+) {
+    let concurrency = concurrency.max(1);
+    let (item_tx, mut item_rx) = mpsc::channel::<RedirectBatchItem>(concurrency);
+    tokio::spawn(async move {
+        let semaphore = Arc::new(Semaphore::new(concurrency));
+        for (position, url) in urls.into_iter().enumerate() {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+            // The writer gave up; don't start crawls nobody will record
+            if item_tx.is_closed() {
+                break;
+            }
+            let (item_tx, parser, crawler) = (item_tx.clone(), parser.clone(), crawler.clone());
+            tokio::spawn(async move {
+                let result = match ParsedUrl::with_config(&url, &parser) {
+                    Ok(parsed) => crawler.crawl(&parsed.anonymized_url).await,
+                    Err(e) => Err(e),
+                };
+                let mut item = RedirectBatchItem::new(url, result);
+                item.position = Some(position);
+                let _ = item_tx.send(item).await;
+                drop(permit);
+            });
+        }
+    });
+
+    let mut writer = BufWriter::new(file);
+    let outcome: std::io::Result<()> = async {
+        while let Some(item) = item_rx.recv().await {
+            let mut line = serde_json::to_vec(&item)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            let mut status = status.lock().unwrap_or_else(|e| e.into_inner());
+            status.completed += 1;
+            if item.error.is_some() {
+                status.failed += 1;
+            }
+        }
+        writer.flush().await
+    }.await;
+
+    let mut status = status.lock().unwrap_or_else(|e| e.into_inner());
+    status.finished_at = Some(Utc::now());
+    match outcome {
+        Ok(()) => {
+            info!("Batch job {} wrote {} results to {}", status.job_id, status.completed, status.output_path);
+            status.state = BatchJobState::Completed;
+        }
+        Err(e) => {
+            warn!("Batch job {} failed writing {}: {}", status.job_id, status.output_path, e);
+            status.state = BatchJobState::Failed;
+            status.error = Some(e.to_string());
+        }
+    }
+}
+
+pub(super) async fn batch_job_handler(path: web::Path<String>, jobs: web::Data<BatchJobs>) -> impl Responder {
+    let job_id = path.into_inner();
+    if !is_valid_request_id(&job_id) {
+        return HttpResponse::BadRequest().body("Invalid job id.");
+    }
+    match jobs.status(&job_id) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body(format!("No batch job {}.", job_id)),
+    }
+}
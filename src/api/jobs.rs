@@ -0,0 +1,137 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::batch::MAX_TRACKED_BATCH_JOBS;
+use super::{admin_rejection, ApiConfig, BatchJobStatus, BatchJobs};
+
+/// Jobs of each kind listed by `GET /admin/jobs` unless the caller asks for fewer.
+const DEFAULT_JOB_LIST_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenshotJobState {
+    /// Waiting for a worker
+    Queued,
+    /// Picked up by a worker
+    Processing,
+}
+
+/// A `POST /screenshot` job the server is working on, as listed by
+/// `GET /admin/jobs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenshotJobStatus {
+    /// The `request_id` its response will carry
+    pub request_id: String,
+    /// The submitted URL, masked like in the logs (see `ParserConfig::redact_logs`)
+    pub url: String,
+    pub state: ScreenshotJobState,
+    pub enqueued_at: DateTime<Utc>,
+    /// When a worker picked it up
+    pub started_at: Option<DateTime<Utc>>,
+    /// Time since it was queued
+    pub elapsed_ms: u64,
+}
+
+/// `POST /screenshot` jobs that are queued or being processed, shared by the
+/// handlers and workers. Finished jobs are not kept; their responses are in
+/// the archive, if enabled.
+#[derive(Clone, Default)]
+pub struct ScreenshotJobs {
+    jobs: Arc<Mutex<HashMap<String, ScreenshotJobStatus>>>,
+}
+
+impl ScreenshotJobs {
+    /// Starts tracking a job as queued; it is listed until the returned
+    /// handle is dropped.
+    pub fn track(&self, request_id: String, url: String) -> TrackedJob {
+        let status = ScreenshotJobStatus {
+            request_id: request_id.clone(),
+            url,
+            state: ScreenshotJobState::Queued,
+            enqueued_at: Utc::now(),
+            started_at: None,
+            elapsed_ms: 0,
+        };
+        self.lock().insert(request_id.clone(), status);
+        TrackedJob { request_id, jobs: self.clone() }
+    }
+
+    /// Up to `limit` tracked jobs, newest first.
+    pub fn list(&self, limit: usize) -> Vec<ScreenshotJobStatus> {
+        let now = Utc::now();
+        let mut jobs: Vec<ScreenshotJobStatus> = self.lock().values()
+            .map(|job| ScreenshotJobStatus {
+                elapsed_ms: (now - job.enqueued_at).num_milliseconds().max(0) as u64,
+                ..job.clone()
+            })
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.enqueued_at));
+        jobs.truncate(limit);
+        jobs
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, ScreenshotJobStatus>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A job's entry in `ScreenshotJobs`, removed when this is dropped: once the
+/// worker is done, or with the job itself if it never reaches one.
+pub struct TrackedJob {
+    request_id: String,
+    jobs: ScreenshotJobs,
+}
+
+impl TrackedJob {
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    pub fn set_processing(&self) {
+        if let Some(job) = self.jobs.lock().get_mut(&self.request_id) {
+            job.state = ScreenshotJobState::Processing;
+            job.started_at = Some(Utc::now());
+        }
+    }
+}
+
+impl Drop for TrackedJob {
+    fn drop(&mut self) {
+        self.jobs.lock().remove(&self.request_id);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobList {
+    screenshot_jobs: Vec<ScreenshotJobStatus>,
+    batch_jobs: Vec<BatchJobStatus>,
+}
+
+/// Lists what the server is working on, for operators: `/screenshot` jobs
+/// that are queued or being processed, and streamed `/redirects` batches that
+/// are running or recently finished, each newest first. `?limit=` caps both
+/// lists (default 100).
+pub(super) async fn jobs_handler(
+    req: HttpRequest,
+    query: web::Query<JobsQuery>,
+    config: web::Data<ApiConfig>,
+    screenshot_jobs: web::Data<ScreenshotJobs>,
+    batch_jobs: web::Data<BatchJobs>,
+) -> HttpResponse {
+    if let Some(rejection) = admin_rejection(&req, &config) {
+        return rejection;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_JOB_LIST_LIMIT).min(MAX_TRACKED_BATCH_JOBS as usize);
+    HttpResponse::Ok().json(JobList {
+        screenshot_jobs: screenshot_jobs.list(limit),
+        batch_jobs: batch_jobs.list(limit),
+    })
+}
@@ -0,0 +1,70 @@
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds of the redirect hop histogram buckets; longer chains only
+/// count towards `+Inf`.
+const HOP_BUCKETS: [u64; 9] = [0, 1, 2, 3, 4, 5, 7, 10, 15];
+
+/// Counters served at `GET /metrics` in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Analyses per bucket of `HOP_BUCKETS`, the last one for longer chains
+    hop_buckets: [AtomicU64; HOP_BUCKETS.len() + 1],
+    hop_sum: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts one analyzed URL whose chain followed `hops` redirects.
+    pub fn record_hops(&self, hops: usize) {
+        let hops = hops as u64;
+        let bucket = HOP_BUCKETS.iter().position(|&bound| hops <= bound).unwrap_or(HOP_BUCKETS.len());
+        self.hop_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.hop_sum.fetch_add(hops, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP screenshot_api_redirect_hops Redirects followed per analyzed URL.\n");
+        out.push_str("# TYPE screenshot_api_redirect_hops histogram\n");
+        // Prometheus buckets are cumulative: each counts every chain up to its bound
+        let mut cumulative = 0;
+        for (bound, count) in HOP_BUCKETS.iter().zip(&self.hop_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "screenshot_api_redirect_hops_bucket{{le=\"{}\"}} {}", bound, cumulative);
+        }
+        cumulative += self.hop_buckets[HOP_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "screenshot_api_redirect_hops_bucket{{le=\"+Inf\"}} {}", cumulative);
+        let _ = writeln!(out, "screenshot_api_redirect_hops_sum {}", self.hop_sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "screenshot_api_redirect_hops_count {}", cumulative);
+        out
+    }
+}
+
+pub(super) async fn metrics_handler(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hop_histogram() {
+        let metrics = Metrics::default();
+        for hops in [0, 3, 4, 4, 40] {
+            metrics.record_hops(hops);
+        }
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE screenshot_api_redirect_hops histogram\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_bucket{le=\"0\"} 1\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_bucket{le=\"2\"} 1\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_bucket{le=\"4\"} 4\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_bucket{le=\"15\"} 4\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_bucket{le=\"+Inf\"} 5\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_sum 51\n"));
+        assert!(rendered.contains("screenshot_api_redirect_hops_count 5\n"));
+    }
+}
@@ -1,41 +1,276 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+mod batch;
+mod jobs;
+mod metrics;
+
+pub use batch::{BatchJobState, BatchJobStatus, BatchJobs};
+pub use jobs::{ScreenshotJobState, ScreenshotJobStatus, ScreenshotJobs, TrackedJob};
+pub use metrics::Metrics;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header::AUTHORIZATION;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
-use log::info;
-use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use moka::sync::Cache;
+use std::time::{Duration, Instant};
 use tokio::time::timeout;
-use crate::url_parser::ParsedUrl;
-use crate::url_crawler::crawl_redirect_chain;
-use crate::screenshot::{ScreenshotTaker, MAX_CONNECTIONS};
-use crate::utils::url_to_snake_case;
+use crate::url_parser::{suspected_lookalikes, validate_url, ParsedUrl, ParserConfig};
+use crate::url_crawler::{crawl_multiple_with_crawler, Crawler, CrawlerConfig, DeniedUrl, DnsCache, RedirectHop, RedirectResult, TerminationReason, UrlDenylist};
+use crate::screenshot::{perceptual_hash, validate_device_pixel_ratio, validate_host_header, validate_interactions, validate_locale, validate_output_subdir, validate_timezone, validate_viewport, CaptureFailure, CaptureOptions, ChromePreferences, Interaction, NavigationErrorPolicy, ScreenshotBackend, ScreenshotConfig, ScreenshotTaker, WebDriverCredentials, MAX_CONNECTIONS};
+use crate::utils::{normalize_url, url_to_snake_case};
+use crate::utils::anonymizer::loggable_url;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 use tokio::sync::{mpsc, oneshot};
 
 const QUEUE_SIZE: usize = 2;
+/// Upper bound on the up-front URL validation done before enqueueing.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(2);
+/// Upper bound on the number of responses kept by `ResponseCache`.
+const MAX_CACHED_RESPONSES: u64 = 1000;
+/// Redirects from which a chain counts as long in `AnalysisSummary::risk`.
+const LONG_REDIRECT_CHAIN: usize = 3;
+/// Distinct hosts from which a chain counts as spread out in `AnalysisSummary::risk`.
+const MANY_REDIRECT_HOSTS: usize = 3;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ScreenshotRequest {
     url: String,
+    /// Per-request deadline in seconds, clamped to `ApiConfig::max_request_timeout`
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    /// Scroll offset `[x, y]` applied before each capture
+    #[serde(default)]
+    scroll_to: Option<(i64, i64)>,
+    /// Subdirectory of `screenshot_dir` to store this request's captures in
+    #[serde(default)]
+    output_subdir: Option<String>,
+    /// Capture the full page instead of just the viewport
+    #[serde(default)]
+    full_page: bool,
+    /// Report `http://` subresources loaded by HTTPS pages
+    #[serde(default)]
+    detect_mixed_content: bool,
+    /// Window size `[width, height]` for this request's captures
+    #[serde(default)]
+    viewport: Option<(u32, u32)>,
+    /// Return the visible text of the final page as `page_text`
+    #[serde(default)]
+    capture_text: bool,
+    /// Always run a fresh analysis, even if a cached response exists
+    #[serde(default)]
+    no_cache: bool,
+    /// Clicks, typing and pauses performed on each page before capturing
+    #[serde(default)]
+    interactions: Vec<Interaction>,
+    /// IANA time zone the pages see, e.g. `Europe/Berlin`
+    #[serde(default)]
+    timezone: Option<String>,
+    /// BCP 47 locale the pages see, e.g. `de-DE`
+    #[serde(default)]
+    locale: Option<String>,
+    /// Device pixel ratio of the captures, between 0.5 and 4 (e.g. `2` for retina)
+    #[serde(default)]
+    device_pixel_ratio: Option<f64>,
+    /// Host to visit the submitted URL's server as, e.g. to capture an IP
+    /// address as `example.com`
+    #[serde(default)]
+    host_header: Option<String>,
+    /// Pause in milliseconds after the destination page loads, before it is
+    /// captured, instead of `post_load_delay`; clamped to
+    /// `ApiConfig::max_post_navigation_delay`
+    #[serde(default)]
+    post_navigation_delay_ms: Option<u64>,
+    /// Redirects to follow, clamped to `ApiConfig::max_request_hops`
+    /// (defaults to the crawler's `max_hops`)
+    #[serde(default)]
+    max_hops: Option<usize>,
+    /// Follow redirects (the default); `false` only requests the URL itself
+    #[serde(default)]
+    follow_redirects: Option<bool>,
+    /// `screenshot_hash` of an earlier response for the same URL; screenshots
+    /// are only returned if the page changed visually since
+    #[serde(default)]
+    previous_hash: Option<String>,
+    /// Return the base64 screenshots (the default); `false` returns only
+    /// their file paths
+    #[serde(default)]
+    include_image_data: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+/// Where a domain in `ScreenshotResponse::domain_sources` was seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainSource {
+    /// Host of the submitted URL
+    Host,
+    /// Host of a URL embedded in a query parameter
+    Query,
+    /// Host of a URL the chain redirected to
+    Redirect,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ScreenshotResponse {
-    original_url: String,
-    anonymized_url: String,
-    final_url: String,
-    identifiers: Vec<Identifier>,
-    original_screenshot: Option<String>,
-    final_screenshot: Option<String>,
-    status: String,
-    message: Option<String>,
+    /// Random identifier of this analysis, also usable in file names
+    pub request_id: String,
+    pub original_url: String,
+    /// `original_url` after `normalize_url`; every later step works on this
+    pub normalized_url: String,
+    pub anonymized_url: String,
+    pub final_url: String,
+    pub redirect_chain: Vec<RedirectHop>,
+    /// Some `redirect_chain[].body` was cut short or left out by
+    /// `CrawlerConfig::max_captured_body_bytes`
+    pub bodies_truncated: bool,
+    /// Why the redirect chain ended: `completed` means the last URL did not
+    /// redirect, `redirects_disabled` that it did but following was turned off
+    pub termination: Option<TerminationReason>,
+    pub identifiers: Vec<Identifier>,
+    /// Set when the URL had more identifiers than `ParserConfig::max_identifiers`
+    /// and analysis stopped early
+    pub identifiers_truncated: bool,
+    pub tracking_parameters: Vec<String>,
+    /// Domains of the URL, its redirect chain or embedded URLs that imitate
+    /// one of `ApiConfig::lookalike_brands`
+    pub suspected_lookalikes: Vec<String>,
+    /// A URL embedded in the query (`?next=https://...`) points at another
+    /// host, and the redirect chain went there
+    pub open_redirect_suspected: bool,
+    /// Every domain seen during the analysis, with where it was seen
+    pub domain_sources: HashMap<String, Vec<DomainSource>>,
+    pub original_screenshot: Option<String>,
+    pub final_screenshot: Option<String>,
+    /// Where the screenshots were saved under `ApiConfig::screenshot_dir`
+    pub original_screenshot_path: Option<String>,
+    pub final_screenshot_path: Option<String>,
+    /// When the most recent screenshot in this response was taken (the final
+    /// page's, if it was captured)
+    pub captured_at: Option<DateTime<Utc>>,
+    /// Perceptual hash (16 hex digits) of the final screenshot, or of the
+    /// original one when the final page wasn't captured
+    pub screenshot_hash: Option<String>,
+    /// Whether the page differs from the request's `previous_hash` by more
+    /// than `ApiConfig::change_threshold`; unset without `previous_hash`
+    pub changed: Option<bool>,
+    /// What the browser showed when a capture failed (only with
+    /// `capture_on_failure`)
+    pub failure_artifacts: Vec<FailureArtifact>,
+    pub content_type: Option<String>,
+    /// Bytes downloaded by the crawl plus the size of the captured images
+    pub total_bytes: u64,
+    pub mixed_content: Vec<String>,
+    pub page_text: Option<String>,
+    /// How long each stage took, starting with the time spent queued
+    pub timings: Vec<OperationTiming>,
+    /// Whether this response was served from `ResponseCache` instead of a
+    /// fresh capture
+    pub from_cache: bool,
+    /// Verdict derived from the other fields once the analysis completed;
+    /// unset for failed requests
+    pub summary: Option<AnalysisSummary>,
+    pub status: String,
+    pub message: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+/// Quick triage verdict for a completed analysis, computed from the response
+/// fields so consumers don't have to re-derive it.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisSummary {
+    pub redirect_count: usize,
+    /// The URL carried encoded personal data (see `identifiers`)
+    pub sensitive_data_found: bool,
+    pub final_url_https: bool,
+    /// Some hop went from `https://` to plain `http://`
+    pub protocol_downgrade: bool,
+    /// Distinct hosts across the redirect chain
+    pub unique_domain_count: usize,
+    pub lookalike_found: bool,
+    pub open_redirect_suspected: bool,
+    /// Heuristic: one point per finding above (open redirects and lookalikes
+    /// count double, long chains and many hosts count once); 0 is low, 1-2
+    /// medium, 3 or more high
+    pub risk: RiskLevel,
+}
+
+impl AnalysisSummary {
+    pub fn new(response: &ScreenshotResponse) -> Self {
+        let is_https = |url: &str| url.get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"));
+        // A downgrade is any hop from an https URL to a plain http one
+        let protocol_downgrade = response.redirect_chain.windows(2)
+            .any(|pair| is_https(&pair[0].url) && !is_https(&pair[1].url));
+        let mut hosts: Vec<String> = response.redirect_chain.iter()
+            .filter_map(|hop| url::Url::parse(&hop.url).ok()?.host_str().map(str::to_string))
+            .collect();
+        hosts.sort();
+        hosts.dedup();
+
+        let redirect_count = response.redirect_chain.len().saturating_sub(1);
+        let sensitive_data_found = !response.identifiers.is_empty();
+        let final_url_https = is_https(&response.final_url);
+        let lookalike_found = !response.suspected_lookalikes.is_empty();
+        let score = [
+            (protocol_downgrade, 1),
+            (sensitive_data_found, 1),
+            (!final_url_https, 1),
+            (redirect_count >= LONG_REDIRECT_CHAIN, 1),
+            (hosts.len() >= MANY_REDIRECT_HOSTS, 1),
+            (lookalike_found, 2),
+            (response.open_redirect_suspected, 2),
+        ].iter().filter(|(finding, _)| *finding).map(|(_, points)| points).sum::<u32>();
+        let risk = match score {
+            0 => RiskLevel::Low,
+            1..=2 => RiskLevel::Medium,
+            _ => RiskLevel::High,
+        };
+
+        Self {
+            redirect_count,
+            sensitive_data_found,
+            final_url_https,
+            protocol_downgrade,
+            unique_domain_count: hosts.len(),
+            lookalike_found,
+            open_redirect_suspected: response.open_redirect_suspected,
+            risk,
+        }
+    }
+}
+
+/// Best-effort screenshot of a page whose capture failed, for debugging.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureArtifact {
+    /// Which capture failed: `original` or `final`
+    pub kind: String,
+    /// Base64 PNG of whatever the browser was showing
+    pub image_data: String,
+}
+
+/// A named stage of a request and its wall-clock duration.
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationTiming {
+    pub operation: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Identifier {
-    value: String,
-    decoded_value: Option<String>,
-    anonymized_value: Option<String>,
+    pub value: String,
+    pub decoded_value: Option<String>,
+    pub anonymized_value: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -43,27 +278,217 @@ pub struct HealthStatus {
     status: String,
     active_connections: usize,
     total_connections: usize,
+    /// Result of the (cached) backend probe; `null` if the backend can't tell
+    webdriver_reachable: Option<bool>,
     uptime: Duration,
 }
 
+/// Build information returned by `GET /version`.
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    version: &'static str,
+    /// Short hash of the commit the binary was built from, or `unknown`
+    git_commit: &'static str,
+    build_timestamp: Option<DateTime<Utc>>,
+}
+
+impl VersionInfo {
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("GIT_COMMIT"),
+            build_timestamp: env!("BUILD_TIMESTAMP").parse().ok()
+                .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+        }
+    }
+}
+
+/// Representation returned by `POST /screenshot`.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// JSON with base64-encoded screenshots
+    #[default]
+    Json,
+    /// The raw PNG of the final (or only) page, with metadata in headers
+    Binary,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScreenshotQuery {
+    #[serde(default)]
+    format: ResponseFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedirectBatchRequest {
+    urls: Vec<String>,
+    /// Write results to an NDJSON file as they complete instead of returning
+    /// them, for batches too large to hold in memory
+    #[serde(default)]
+    stream_to_file: bool,
+}
+
+/// Redirect chain of one URL of a `POST /redirects` batch. Exactly one of
+/// `final_url` and `error` is set.
+#[derive(Debug, Clone, Serialize)]
+pub struct RedirectBatchItem {
+    url: String,
+    final_url: Option<String>,
+    redirect_chain: Vec<RedirectHop>,
+    termination: Option<TerminationReason>,
+    error: Option<String>,
+    /// Position of an earlier entry with the same normalized URL; this entry
+    /// reuses its result instead of crawling again
+    duplicate_of: Option<usize>,
+    /// Position in the submitted list; only set on streamed results, which
+    /// are written in completion order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+}
+
+impl RedirectBatchItem {
+    fn new(url: String, result: Result<RedirectResult>) -> Self {
+        match result {
+            Ok(result) => Self {
+                url,
+                final_url: result.final_url().map(str::to_string),
+                redirect_chain: result.hops,
+                termination: Some(result.termination),
+                error: None,
+                duplicate_of: None,
+                position: None,
+            },
+            Err(e) => Self {
+                url,
+                final_url: None,
+                redirect_chain: Vec::new(),
+                termination: None,
+                error: Some(format!("{:#}", e)),
+                duplicate_of: None,
+                position: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WarmupQuery {
+    n: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WarmupResponse {
+    created: usize,
+    total_connections: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecycleResponse {
+    closed: usize,
+    created: usize,
+    total_connections: usize,
+}
+
 pub struct ScreenshotJob {
     pub request: ScreenshotRequest,
-    pub response_tx: oneshot::Sender<Result<ScreenshotResponse, String>>,
+    /// The request's URL as the handler already parsed it, so the worker
+    /// doesn't analyze it a second time
+    pub parsed_url: Option<ParsedUrl>,
+    pub timeout: Duration,
+    /// When the handler queued the job, to tell queuing from processing
+    pub enqueued_at: Instant,
+    pub response_tx: oneshot::Sender<Result<ScreenshotResponse>>,
+    /// Lists the job in `GET /admin/jobs` for as long as it exists; its
+    /// `request_id` becomes the response's
+    pub tracking: TrackedJob,
 }
 
 impl ScreenshotResponse {
     fn new(url: String) -> Self {
         Self {
+            request_id: new_request_id(),
             original_url: url,
+            normalized_url: String::new(),
             anonymized_url: String::new(),
             final_url: String::new(),
+            redirect_chain: Vec::new(),
+            bodies_truncated: false,
+            termination: None,
             identifiers: Vec::new(),
+            identifiers_truncated: false,
+            tracking_parameters: Vec::new(),
+            suspected_lookalikes: Vec::new(),
+            open_redirect_suspected: false,
+            domain_sources: HashMap::new(),
             original_screenshot: None,
             final_screenshot: None,
+            original_screenshot_path: None,
+            final_screenshot_path: None,
+            captured_at: None,
+            screenshot_hash: None,
+            changed: None,
+            failure_artifacts: Vec::new(),
+            content_type: None,
+            total_bytes: 0,
+            mixed_content: Vec::new(),
+            page_text: None,
+            timings: Vec::new(),
+            from_cache: false,
+            summary: None,
             status: "pending".to_string(),
             message: None,
         }
     }
+
+    fn record_timing(&mut self, operation: &str, duration: Duration) {
+        self.timings.push(OperationTiming {
+            operation: operation.to_string(),
+            duration_ms: duration.as_millis() as u64,
+        });
+    }
+}
+
+impl ScreenshotRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Identifies requests that would produce the same analysis: the
+    /// normalized URL plus every capture option. `None` if the URL can't be
+    /// normalized.
+    fn cache_key(&self) -> Option<String> {
+        let url = normalize_url(&self.url).ok()?;
+        let options = ScreenshotRequest {
+            url: String::new(),
+            timeout_secs: None,
+            no_cache: false,
+            ..self.clone()
+        };
+        Some(format!("{} {}", url, serde_json::to_string(&options).ok()?))
+    }
+
+    /// The client's `max_hops` clamped to the server ceiling, if it set one.
+    fn effective_max_hops(&self, config: &ApiConfig) -> Option<usize> {
+        self.max_hops.map(|hops| hops.min(config.max_request_hops))
+    }
+
+    /// The client's destination delay clamped to the server ceiling, if it set one.
+    fn effective_post_navigation_delay(&self, config: &ApiConfig) -> Option<Duration> {
+        self.post_navigation_delay_ms.map(|ms| Duration::from_millis(ms).min(config.max_post_navigation_delay))
+    }
+
+    /// Deadline for this request: the client's `timeout_secs` clamped to the
+    /// server maximum, or the server default when unspecified.
+    fn effective_timeout(&self, config: &ApiConfig) -> Duration {
+        match self.timeout_secs {
+            Some(secs) => Duration::from_secs(secs.max(1)).min(config.max_request_timeout),
+            None => config.request_timeout,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -73,20 +498,221 @@ pub struct ApiConfig {
     pub viewport_height: u32,
     pub headless: bool,
     pub webdriver_url: Option<String>,
+    /// Warm standby WebDriver endpoints, used when `webdriver_url` keeps
+    /// refusing new sessions
+    pub webdriver_urls: Vec<String>,
+    pub webdriver_credentials: Option<WebDriverCredentials>,
     pub request_timeout: Duration,
+    pub max_request_timeout: Duration,
+    pub crawler: CrawlerConfig,
+    /// Upper bound on a request's `max_hops`
+    pub max_request_hops: usize,
+    pub parser: ParserConfig,
+    /// Brand domains (e.g. `paypal.com`) whose lookalikes are reported in
+    /// `suspected_lookalikes`
+    pub lookalike_brands: Vec<String>,
+    /// Hosts or URL patterns never crawled or screenshotted (403); see
+    /// `UrlDenylist` for the pattern syntax
+    pub denylist: Vec<String>,
+    /// Refuse (403) URLs and redirects whose host resolves to a private,
+    /// loopback or link-local address, and discard screenshots of pages that
+    /// end up on or load from one
+    pub block_private_ips: bool,
+    /// How long hostname lookups are shared across requests by one
+    /// server-wide `DnsCache`, unless `crawler.dns_cache` is already set;
+    /// lookups are not cached when unset
+    pub dns_cache_ttl: Option<Duration>,
+    pub max_screenshot_bytes: usize,
+    pub navigation_error_policy: NavigationErrorPolicy,
+    pub post_load_delay: Duration,
+    /// Upper bound on a request's `post_navigation_delay_ms`
+    pub max_post_navigation_delay: Duration,
+    /// Chrome content settings (images, JavaScript, geolocation, ...)
+    pub chrome_preferences: ChromePreferences,
+    /// Extra WebDriver capabilities, e.g. `browserName` or cloud-grid options
+    pub extra_capabilities: serde_json::Map<String, serde_json::Value>,
+    /// Name of saved captures, e.g. `{domain}_{kind}_{request_id}`; see
+    /// `validate_filename_template`
+    pub filename_template: String,
+    /// Media types the browser is pointed at; anything else is reported but not captured
+    pub screenshot_content_types: Vec<String>,
+    /// Bearer token for `/admin/*` endpoints; they are disabled when unset
+    pub admin_token: Option<String>,
+    /// Maximum accepted request body size in bytes
+    pub max_payload_bytes: usize,
+    /// Return a screenshot of whatever the browser shows when a capture
+    /// fails, in `failure_artifacts`
+    pub capture_on_failure: bool,
+    /// Most screenshots one request may produce, failure screenshots
+    /// included. The original is captured first, then the destination, and
+    /// failure screenshots get what's left; anything beyond the cap is
+    /// skipped with a note in `message`
+    pub max_screenshots_per_request: usize,
+    /// How long successful responses are reused for identical requests;
+    /// caching is disabled when unset
+    pub response_cache_ttl: Option<Duration>,
+    /// Directory where every completed response is stored as
+    /// `{request_id}.json` and served by `GET /archive/{request_id}`;
+    /// archiving is disabled when unset
+    pub archive_dir: Option<String>,
+    /// Most URLs accepted by one `POST /redirects` request
+    pub max_redirect_batch: usize,
+    /// Crawls run in parallel for a `POST /redirects` request
+    pub redirect_batch_concurrency: usize,
+    /// Directory where `POST /redirects` batches with `stream_to_file` write
+    /// their results as `{job_id}.ndjson`; streaming is disabled when unset
+    pub batch_output_dir: Option<String>,
+    /// Most URLs accepted by one streamed `POST /redirects` request
+    pub max_streamed_batch: usize,
+    /// Bits (out of 64) by which `screenshot_hash` may differ from a
+    /// request's `previous_hash` before the page counts as changed
+    pub change_threshold: u32,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            screenshot_dir: "screenshots".to_string(),
+            viewport_width: 1280,
+            viewport_height: 800,
+            headless: true,
+            webdriver_url: None,
+            webdriver_urls: Vec::new(),
+            webdriver_credentials: None,
+            request_timeout: Duration::from_secs(30),
+            max_request_timeout: Duration::from_secs(120),
+            crawler: CrawlerConfig::default(),
+            max_request_hops: 30,
+            parser: ParserConfig::default(),
+            lookalike_brands: Vec::new(),
+            denylist: Vec::new(),
+            block_private_ips: false,
+            dns_cache_ttl: None,
+            max_screenshot_bytes: ScreenshotConfig::default().max_screenshot_bytes,
+            navigation_error_policy: NavigationErrorPolicy::default(),
+            post_load_delay: ScreenshotConfig::default().post_load_delay,
+            max_post_navigation_delay: Duration::from_secs(30),
+            chrome_preferences: ChromePreferences::default(),
+            extra_capabilities: serde_json::Map::new(),
+            filename_template: ScreenshotConfig::default().filename_template,
+            screenshot_content_types: vec!["text/html".to_string()],
+            admin_token: None,
+            max_payload_bytes: 256 * 1024,
+            capture_on_failure: false,
+            max_screenshots_per_request: 4,
+            response_cache_ttl: None,
+            archive_dir: None,
+            max_redirect_batch: 1000,
+            redirect_batch_concurrency: 20,
+            batch_output_dir: None,
+            max_streamed_batch: 100_000,
+            change_threshold: 5,
+        }
+    }
+}
+
+impl ApiConfig {
+    /// `crawler` with the server-wide `denylist` and `block_private_ips`
+    /// added to its own, redacting its logs as `parser.redact_logs` says.
+    /// Without a `crawler.dns_cache`, one is created from `dns_cache_ttl`;
+    /// the server does this once at startup so all its crawlers share it.
+    pub fn crawler_config(&self) -> CrawlerConfig {
+        let mut crawler = self.crawler.clone();
+        crawler.denylist.extend(self.denylist.iter().cloned());
+        crawler.block_private_ips |= self.block_private_ips;
+        crawler.redact_logs = self.parser.redact_logs;
+        if crawler.dns_cache.is_none() {
+            crawler.dns_cache = self.dns_cache_ttl.map(DnsCache::new);
+        }
+        crawler
+    }
+
+    /// `url` as it may appear in logs, with credentials and query values masked unless
+    /// `parser.redact_logs` is off.
+    fn loggable<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        loggable_url(url, self.parser.redact_logs)
+    }
+
+    pub fn screenshot_config(&self) -> ScreenshotConfig {
+        ScreenshotConfig {
+            screenshot_dir: self.screenshot_dir.clone(),
+            webdriver_url: self.webdriver_url.clone(),
+            webdriver_urls: self.webdriver_urls.clone(),
+            viewport_size: Some((self.viewport_width, self.viewport_height)),
+            headless: self.headless,
+            webdriver_credentials: self.webdriver_credentials.clone(),
+            max_screenshot_bytes: self.max_screenshot_bytes,
+            navigation_error_policy: self.navigation_error_policy,
+            post_load_delay: self.post_load_delay,
+            chrome_preferences: self.chrome_preferences,
+            extra_capabilities: self.extra_capabilities.clone(),
+            filename_template: self.filename_template.clone(),
+            capture_on_failure: self.capture_on_failure,
+            redact_logs: self.parser.redact_logs,
+        }
+    }
+}
+
+fn is_screenshot_content_type(content_type: Option<&str>, allowed: &[String]) -> bool {
+    // Servers that omit Content-Type are given the benefit of the doubt
+    match content_type {
+        Some(content_type) => allowed.iter().any(|a| a.eq_ignore_ascii_case(content_type)),
+        None => true,
+    }
+}
+
+/// Runs the full pipeline for a single URL (parse and anonymize, crawl the
+/// redirect chain, screenshot the original and final pages) without starting
+/// the HTTP server. A fresh `Crawler` is built from `crawler_config()`; the
+/// screenshot backend is borrowed so a `ScreenshotTaker`'s connection pool can
+/// be reused across calls.
+pub async fn analyze_url(url: &str, config: &ApiConfig, screenshot_taker: &dyn ScreenshotBackend) -> Result<ScreenshotResponse> {
+    let crawler = Crawler::new(config.crawler_config())?;
+    process_request(ScreenshotRequest::new(url), config, screenshot_taker, &crawler).await
 }
 
 async fn process_request(
     request: ScreenshotRequest,
-    _config: &ApiConfig,
-    screenshot_taker: Arc<ScreenshotTaker>,
+    config: &ApiConfig,
+    screenshot_taker: &dyn ScreenshotBackend,
+    crawler: &Crawler,
+) -> Result<ScreenshotResponse> {
+    process_parsed_request(request, None, None, config, screenshot_taker, crawler).await
+}
+
+/// `request.url` after `normalize_url`. Unparseable input is left as is so
+/// the parser reports why it's invalid.
+fn normalized_request_url(request: &ScreenshotRequest) -> String {
+    normalize_url(&request.url).unwrap_or_else(|_| request.url.clone())
+}
+
+/// `process_request` for a URL the handler may have parsed already (see
+/// `ScreenshotJob::parsed_url`); it is parsed here otherwise. The response
+/// gets `request_id` if the job already has one.
+async fn process_parsed_request(
+    request: ScreenshotRequest,
+    parsed_url: Option<ParsedUrl>,
+    request_id: Option<String>,
+    config: &ApiConfig,
+    screenshot_taker: &dyn ScreenshotBackend,
+    crawler: &Crawler,
 ) -> Result<ScreenshotResponse> {
+    let deadline = Instant::now() + request.effective_timeout(config);
     let mut response = ScreenshotResponse::new(request.url.clone());
+    if let Some(request_id) = request_id {
+        response.request_id = request_id;
+    }
     
-    // Step 1: Parse and anonymize the URL
-    info!("Parsing URL: {}", request.url);
-    let parsed_url = ParsedUrl::new(&request.url)?;
+    // Step 1: Normalize, parse and anonymize the URL
+    response.normalized_url = normalized_request_url(&request);
+    info!("Parsing URL for request {}: {}", response.request_id, config.loggable(&response.normalized_url));
+    let parsed_url = match parsed_url {
+        Some(parsed_url) => parsed_url,
+        None => ParsedUrl::with_config(&response.normalized_url, &config.parser)?,
+    };
     response.anonymized_url = parsed_url.anonymized_url.clone();
+    response.tracking_parameters = parsed_url.tracking_parameters.clone();
+    response.identifiers_truncated = parsed_url.identifiers_truncated;
     
     // Add identifiers to response
     for identifier in &parsed_url.identifiers {
@@ -98,118 +724,830 @@ async fn process_request(
     }
 
     // Step 2: Check redirect chain
-    info!("Checking redirect chain for: {}", parsed_url.anonymized_url);
-    let redirect_chain = crawl_redirect_chain(&parsed_url.anonymized_url).await?;
-    if let Some(final_url) = redirect_chain.last() {
-        response.final_url = final_url.clone();
+    info!("Checking redirect chain for: {}", config.loggable(&parsed_url.anonymized_url));
+    let crawl_started = Instant::now();
+    let host_crawler;
+    let crawler = match &request.host_header {
+        Some(host) => {
+            host_crawler = crawler.with_host_header(host);
+            &host_crawler
+        }
+        None => crawler,
+    };
+    let redirect_result = match request.effective_max_hops(config) {
+        _ if request.follow_redirects == Some(false) => crawler.without_redirects().crawl(&parsed_url.anonymized_url).await?,
+        Some(max_hops) => crawler.with_max_hops(max_hops).crawl(&parsed_url.anonymized_url).await?,
+        None => crawler.crawl(&parsed_url.anonymized_url).await?,
+    };
+    response.record_timing("crawl", crawl_started.elapsed());
+    info!("Redirect chain has {} URLs (stopped: {:?})", redirect_result.chain.len(), redirect_result.termination);
+    if let Some(final_url) = redirect_result.final_url() {
+        response.final_url = final_url.to_string();
+    }
+    response.redirect_chain = redirect_result.hops.clone();
+    response.bodies_truncated = redirect_result.bodies_truncated;
+    response.termination = Some(redirect_result.termination);
+    response.total_bytes = redirect_result.bytes_transferred;
+
+    let urls = std::iter::once(&parsed_url.anonymized_url)
+        .chain(redirect_result.chain.iter())
+        .chain(parsed_url.referenced_urls.iter());
+    let domains: Vec<String> = urls
+        .filter_map(|url| url::Url::parse(url).ok()?.host_str().map(str::to_string))
+        .collect();
+    response.suspected_lookalikes = suspected_lookalikes(domains.iter().map(String::as_str), &config.lookalike_brands);
+    response.open_redirect_suspected = open_redirect_suspected(
+        &parsed_url.anonymized_url,
+        &parsed_url.referenced_urls,
+        &redirect_result.chain,
+    );
+    response.domain_sources = domain_sources(
+        &parsed_url.anonymized_url,
+        &parsed_url.referenced_urls,
+        &redirect_result.chain,
+    );
+
+    // Binary destinations (PDFs, archives, images) render poorly and waste a
+    // browser slot; report the content type instead of navigating to them.
+    // The original URL is still captured unless it is the destination itself.
+    response.content_type = redirect_result.final_content_type.clone();
+    let capture_destination = is_screenshot_content_type(response.content_type.as_deref(), &config.screenshot_content_types);
+    let final_url = redirect_result.final_url().filter(|final_url| *final_url != parsed_url.anonymized_url);
+    if !capture_destination && final_url.is_none() {
+        info!("Skipping screenshots for non-HTML destination ({:?})", response.content_type);
+        response.status = "skipped".to_string();
+        response.message = Some(format!(
+            "Screenshots skipped: destination serves {}",
+            response.content_type.as_deref().unwrap_or("unknown content")
+        ));
+        response.summary = Some(AnalysisSummary::new(&response));
+        log_siem_summary(&response);
+        return Ok(response);
     }
 
     // Step 3: Take screenshots
+    let capture_options = CaptureOptions {
+        scroll_to: request.scroll_to,
+        output_subdir: request.output_subdir.clone(),
+        full_page: request.full_page,
+        detect_mixed_content: request.detect_mixed_content,
+        viewport: request.viewport,
+        capture_text: request.capture_text,
+        deadline: Some(deadline),
+        request_id: Some(response.request_id.clone()),
+        kind: None,
+        interactions: request.interactions.clone(),
+        timezone: request.timezone.clone(),
+        locale: request.locale.clone(),
+        device_pixel_ratio: request.device_pixel_ratio,
+        host_header: request.host_header.clone(),
+        post_load_delay: None,
+        skip_failure_capture: false,
+        block_private_ips: crawler.blocks_private_ips(),
+    };
     let base_name = url_to_snake_case(&parsed_url.anonymized_url);
-    
-    // Take screenshot of original URL
-    let original_screenshot = screenshot_taker.take_screenshot(
-        &parsed_url.anonymized_url,
-        &format!("{}_original", base_name)
-    ).await?;
-    response.original_screenshot = Some(original_screenshot.image_data);
+    let screenshots_started = Instant::now();
+    // The cap is spent on the original first, then the destination; failure
+    // screenshots only get the slots left over
+    let cap = config.max_screenshots_per_request;
+    let planned_captures = (1 + usize::from(final_url.is_some() && capture_destination)).min(cap);
+    let failure_slots = cap - planned_captures;
+    let failure_capture_skipped = |response: &ScreenshotResponse| {
+        (config.capture_on_failure && response.failure_artifacts.len() >= failure_slots).then_some(cap)
+    };
+
+    // Take screenshot of original URL. A failed capture degrades the response
+    // to "partial" instead of discarding the redirect data gathered above.
+    if planned_captures == 0 {
+        add_message(&mut response, format!("Original screenshot skipped: at most {} screenshots per request", cap));
+    } else {
+        let skipped = failure_capture_skipped(&response);
+        match screenshot_taker.take_screenshot(
+            &parsed_url.anonymized_url,
+            &format!("{}_original", base_name),
+            &CaptureOptions {
+                kind: Some("original".to_string()),
+                skip_failure_capture: skipped.is_some(),
+                ..capture_options.clone()
+            }
+        ).await {
+            Ok(original_screenshot) => {
+                response.total_bytes += original_screenshot.byte_size as u64;
+                response.mixed_content.extend(original_screenshot.mixed_content);
+                response.page_text = original_screenshot.page_text;
+                response.captured_at = Some(original_screenshot.captured_at);
+                response.original_screenshot = Some(original_screenshot.image_data);
+                response.original_screenshot_path = Some(original_screenshot.file_path);
+            }
+            Err(e) => record_screenshot_failure(&mut response, "original", &e, skipped),
+        }
+    }
 
     // Take screenshot of final URL if different
-    if let Some(final_url) = redirect_chain.last() {
-        if final_url != &parsed_url.anonymized_url {
+    if let Some(final_url) = final_url {
+        if !capture_destination {
+            info!("Skipping destination screenshot for non-HTML destination ({:?})", response.content_type);
+            let note = format!(
+                "Destination screenshot skipped: destination serves {}",
+                response.content_type.as_deref().unwrap_or("unknown content")
+            );
+            add_message(&mut response, note);
+        } else if planned_captures < 2 {
+            add_message(&mut response, format!("Destination screenshot skipped: at most {} screenshots per request", cap));
+        } else {
             let dest_name = url_to_snake_case(final_url);
-            let final_screenshot = screenshot_taker.take_screenshot(
+            let skipped = failure_capture_skipped(&response);
+            // Like the crawler, only the submitted URL's server is visited as `host_header`
+            let host_header = capture_options.host_header.clone()
+                .filter(|_| same_origin(&parsed_url.anonymized_url, final_url));
+            match screenshot_taker.take_screenshot(
                 final_url,
-                &format!("{}_destination", dest_name)
-            ).await?;
-            response.final_screenshot = Some(final_screenshot.image_data);
+                &format!("{}_destination", dest_name),
+                &CaptureOptions {
+                    kind: Some("destination".to_string()),
+                    post_load_delay: request.effective_post_navigation_delay(config),
+                    skip_failure_capture: skipped.is_some(),
+                    host_header,
+                    ..capture_options.clone()
+                }
+            ).await {
+                Ok(final_screenshot) => {
+                    response.total_bytes += final_screenshot.byte_size as u64;
+                    response.mixed_content.extend(final_screenshot.mixed_content);
+                    // The destination's text is what the victim actually sees
+                    if final_screenshot.page_text.is_some() {
+                        response.page_text = final_screenshot.page_text;
+                    }
+                    response.final_screenshot = Some(final_screenshot.image_data);
+                    response.final_screenshot_path = Some(final_screenshot.file_path);
+                    response.captured_at = Some(final_screenshot.captured_at);
+                }
+                Err(e) => record_screenshot_failure(&mut response, "final", &e, skipped),
+            }
         }
     }
 
-    response.status = "success".to_string();
+    response.record_timing("screenshots", screenshots_started.elapsed());
+    detect_change(&mut response, request.previous_hash.as_deref(), config.change_threshold);
+    if request.include_image_data == Some(false) {
+        response.original_screenshot = None;
+        response.final_screenshot = None;
+    }
+
+    response.mixed_content.sort();
+    response.mixed_content.dedup();
+
+    if response.status == "pending" {
+        response.status = "success".to_string();
+    }
+    response.summary = Some(AnalysisSummary::new(&response));
+    log_siem_summary(&response);
     Ok(response)
 }
 
+/// Sets `screenshot_hash`, and with a `previous_hash` also `changed`. An
+/// unchanged page's screenshots are dropped, since the caller already has
+/// them; if no hash could be computed the page counts as changed.
+fn detect_change(response: &mut ScreenshotResponse, previous_hash: Option<&str>, threshold: u32) {
+    let hash = response.final_screenshot.as_ref()
+        .or(response.original_screenshot.as_ref())
+        .and_then(|image_data| BASE64.decode(image_data).ok())
+        .and_then(|png| perceptual_hash(&png).map_err(|e| warn!("Failed to hash screenshot: {:#}", e)).ok());
+    response.screenshot_hash = hash.map(|hash| format!("{:016x}", hash));
+
+    let Some(previous) = previous_hash.and_then(|previous| u64::from_str_radix(previous, 16).ok()) else {
+        return;
+    };
+    let changed = match hash {
+        Some(hash) => (hash ^ previous).count_ones() > threshold,
+        None => true,
+    };
+    response.changed = Some(changed);
+    if !changed {
+        info!("Request {} has not changed visually, omitting screenshots", response.request_id);
+        response.original_screenshot = None;
+        response.final_screenshot = None;
+    }
+}
+
+/// One canonical JSON record per completed analysis, logged under the `siem`
+/// target so it can be routed to a SIEM independently of the regular logs.
+fn log_siem_summary(response: &ScreenshotResponse) {
+    info!(target: "siem", "{}", siem_summary(response));
+}
+
+/// Reads the verdict from `response.summary`; its fields are `null` when unset.
+fn siem_summary(response: &ScreenshotResponse) -> serde_json::Value {
+    let summary = response.summary.as_ref();
+    serde_json::json!({
+        "event": "url_analysis_summary",
+        "request_id": response.request_id,
+        "original_url": response.original_url,
+        "final_url": response.final_url,
+        "hop_count": summary.map(|summary| summary.redirect_count),
+        "identifier_count": response.identifiers.len(),
+        "final_url_https": summary.map(|summary| summary.final_url_https),
+        "protocol_downgrade": summary.map(|summary| summary.protocol_downgrade),
+        "suspected_lookalikes": response.suspected_lookalikes,
+        "open_redirect_suspected": response.open_redirect_suspected,
+        "risk": summary.map(|summary| summary.risk),
+        "status": response.status,
+    })
+}
+
+/// Whether `a` and `b` have the same origin (scheme, host and port).
+fn same_origin(a: &str, b: &str) -> bool {
+    let origin = |url: &str| url::Url::parse(url).ok().map(|url| url.origin());
+    matches!((origin(a), origin(b)), (Some(a), Some(b)) if a == b)
+}
+
+/// Whether the redirect chain of `start_url` reached the host of one of its
+/// `referenced_urls` (other than its own host), meaning a query parameter
+/// decided where the site redirected to.
+fn open_redirect_suspected(start_url: &str, referenced_urls: &[String], chain: &[String]) -> bool {
+    let host = |url: &str| url::Url::parse(url).ok()?.host_str().map(str::to_string);
+    let start_host = host(start_url);
+    let referenced_hosts: Vec<String> = referenced_urls.iter()
+        .filter_map(|url| host(url))
+        .filter(|referenced| Some(referenced) != start_host.as_ref())
+        .collect();
+    chain.iter().skip(1)
+        .filter_map(|url| host(url))
+        .any(|hop_host| referenced_hosts.contains(&hop_host))
+}
+
+/// The hosts of `start_url`, its `referenced_urls` and the redirect targets in
+/// `chain`, each with the (sorted, distinct) places it appeared.
+fn domain_sources(start_url: &str, referenced_urls: &[String], chain: &[String]) -> HashMap<String, Vec<DomainSource>> {
+    let sourced = std::iter::once((start_url, DomainSource::Host))
+        .chain(referenced_urls.iter().map(|url| (url.as_str(), DomainSource::Query)))
+        .chain(chain.iter().skip(1).map(|url| (url.as_str(), DomainSource::Redirect)));
+    let mut sources: HashMap<String, Vec<DomainSource>> = HashMap::new();
+    for (url, source) in sourced {
+        let Some(host) = url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) else {
+            continue;
+        };
+        let seen = sources.entry(host).or_default();
+        if !seen.contains(&source) {
+            seen.push(source);
+            seen.sort();
+        }
+    }
+    sources
+}
+
+fn new_request_id() -> String {
+    let bytes: [u8; 8] = rand::random();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Marks the response partial and keeps the failure screenshot, if any. With
+/// `skipped_failure_capture` (the request's screenshot cap) that screenshot
+/// was not allowed; it is dropped and the skip noted instead.
+fn record_screenshot_failure(response: &mut ScreenshotResponse, kind: &str, error: &anyhow::Error, skipped_failure_capture: Option<usize>) {
+    warn!("The {} screenshot failed for request {}: {}", kind, response.request_id, error);
+    add_message(response, format!("{} screenshot failed: {}", kind, error));
+    match (error.downcast_ref::<CaptureFailure>(), skipped_failure_capture) {
+        (_, Some(cap)) => {
+            info!("Skipping the {} failure screenshot of request {}: cap of {} reached", kind, response.request_id, cap);
+            add_message(response, format!("{} failure screenshot skipped: at most {} screenshots per request", kind, cap));
+        }
+        (Some(failure), None) => response.failure_artifacts.push(FailureArtifact {
+            kind: kind.to_string(),
+            image_data: failure.artifact.clone(),
+        }),
+        (None, None) => {}
+    }
+    response.status = "partial".to_string();
+}
+
+/// Appends `note` to the response's `message`, after any earlier notes.
+fn add_message(response: &mut ScreenshotResponse, note: String) {
+    response.message = Some(match response.message.take() {
+        Some(existing) => format!("{}; {}", existing, note),
+        None => note,
+    });
+}
+
 async fn screenshot_handler(
+    query: web::Query<ScreenshotQuery>,
     request: web::Json<ScreenshotRequest>,
     config: web::Data<ApiConfig>,
+    denylist: web::Data<UrlDenylist>,
+    cache: web::Data<ResponseCache>,
     job_tx: web::Data<mpsc::Sender<ScreenshotJob>>,
+    screenshot_jobs: web::Data<ScreenshotJobs>,
 ) -> impl Responder {
     let (response_tx, response_rx) = oneshot::channel();
+    let mut request = request.into_inner();
+    if let Err(denied) = denylist.check(&request.url) {
+        warn!("Refusing request for {}: it matched '{}'", config.loggable(&denied.url), denied.pattern);
+        return HttpResponse::Forbidden().body(denied.to_string());
+    }
+    // Cheap syntactic checks first, so their errors aren't blamed on analysis
+    if let Err(e) = validate_url(&request.url) {
+        return HttpResponse::BadRequest().body(format!("Invalid URL: {}", e));
+    }
+    // Parsed as the worker would, which then reuses the result
+    let url = normalized_request_url(&request);
+    let parser = config.parser.clone();
+    let validation = run_validation(VALIDATION_TIMEOUT, move || ParsedUrl::with_config(&url, &parser));
+    let parsed_url = match validation.await {
+        Ok(parsed_url) => parsed_url,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    if let Some(subdir) = &request.output_subdir {
+        if let Err(e) = validate_output_subdir(subdir) {
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+    }
+    if let Err(e) = validate_interactions(&request.interactions) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    if let Some(Err(e)) = request.timezone.as_deref().map(validate_timezone) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    if let Some(Err(e)) = request.locale.as_deref().map(validate_locale) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    if let Some(Err(e)) = request.device_pixel_ratio.map(validate_device_pixel_ratio) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    if let Some(Err(e)) = request.host_header.as_deref().map(validate_host_header) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    if request.include_image_data == Some(false) && matches!(query.format, ResponseFormat::Binary) {
+        return HttpResponse::BadRequest().body("format=binary needs the image data; drop include_image_data=false");
+    }
+    if let Some(previous_hash) = &request.previous_hash {
+        // Checked by hand: `from_str_radix` would also take a leading `+`
+        if previous_hash.len() != 16 || !previous_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return HttpResponse::BadRequest().body("previous_hash must be 16 hexadecimal digits");
+        }
+    }
+    if let Some((width, height)) = request.viewport {
+        match validate_viewport(width, height) {
+            Ok(viewport) => request.viewport = Some(viewport),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        }
+    }
+    let cache_key = if request.no_cache { None } else { request.cache_key() };
+    if let Some(cached) = cache_key.as_deref().and_then(|key| cache.get(key)) {
+        debug!("Serving {} from the response cache", config.loggable(&request.url));
+        return match query.format {
+            ResponseFormat::Json => HttpResponse::Ok().json(cached),
+            ResponseFormat::Binary => binary_response(&cached),
+        };
+    }
+    let request_timeout = request.effective_timeout(&config);
+    let tracking = screenshot_jobs.track(new_request_id(), config.loggable(&request.url).into_owned());
     let job = ScreenshotJob {
-        request: request.into_inner(),
+        request,
+        parsed_url: Some(parsed_url),
+        timeout: request_timeout,
+        enqueued_at: Instant::now(),
         response_tx,
+        tracking,
     };
 
     // Try to enqueue the job
-    if let Err(_) = job_tx.try_send(job) {
+    if job_tx.try_send(job).is_err() {
         return HttpResponse::TooManyRequests().body("Server is busy, try again later.");
     }
 
     // Wait for the result
-    match timeout(config.request_timeout, response_rx).await {
-        Ok(Ok(Ok(response))) => HttpResponse::Ok().json(response),
-        Ok(Ok(Err(e))) => HttpResponse::InternalServerError().body(e),
+    match timeout(request_timeout, response_rx).await {
+        Ok(Ok(Ok(response))) => {
+            if let Some(key) = cache_key {
+                cache.insert(key, &response);
+            }
+            match query.format {
+                ResponseFormat::Json => HttpResponse::Ok().json(response),
+                ResponseFormat::Binary => binary_response(&response),
+            }
+        }
+        Ok(Ok(Err(e))) => job_error_response(&e),
         Ok(Err(_)) => HttpResponse::InternalServerError().body("Worker dropped."),
         Err(_) => HttpResponse::RequestTimeout().body("Request timed out."),
     }
 }
 
-async fn health_check(screenshot_taker: web::Data<Arc<ScreenshotTaker>>) -> impl Responder {
-    let active = screenshot_taker.active_connections.load(Ordering::SeqCst);
-    let total = screenshot_taker.total_connections.load(Ordering::SeqCst);
-    
-    let status = if active < total {
-        "healthy"
-    } else if active == total {
-        "degraded"
-    } else {
-        "unhealthy"
+/// Successful responses keyed by `ScreenshotRequest::cache_key`, shared by
+/// all handlers. A no-op when `ApiConfig::response_cache_ttl` is unset.
+#[derive(Clone)]
+pub struct ResponseCache {
+    cache: Option<Cache<String, ScreenshotResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        let cache = ttl.map(|ttl| Cache::builder()
+            .max_capacity(MAX_CACHED_RESPONSES)
+            .time_to_live(ttl)
+            .build());
+        Self { cache }
+    }
+
+    fn get(&self, key: &str) -> Option<ScreenshotResponse> {
+        let mut response = self.cache.as_ref()?.get(key)?;
+        response.from_cache = true;
+        Some(response)
+    }
+
+    fn insert(&self, key: String, response: &ScreenshotResponse) {
+        // Partial or skipped analyses are worth retrying rather than replaying
+        if let Some(cache) = &self.cache {
+            if response.status == "success" {
+                cache.insert(key, response.clone());
+            }
+        }
+    }
+}
+
+/// Policy refusals (e.g. a redirect onto a denied host) are 403s; anything
+/// else is an internal error.
+fn job_error_response(error: &anyhow::Error) -> HttpResponse {
+    if let Some(denied) = error.downcast_ref::<DeniedUrl>() {
+        return HttpResponse::Forbidden().body(denied.to_string());
+    }
+    HttpResponse::InternalServerError().body(format!("{:#}", error))
+}
+
+/// Runs a synchronous validation step on the blocking pool, giving up after
+/// `limit` so a pathological input can't stall the handler before the job is
+/// even queued. Errors are returned as the message for a 400 response.
+async fn run_validation<T, F>(limit: Duration, validate: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match timeout(limit, tokio::task::spawn_blocking(validate)).await {
+        Ok(Ok(Ok(value))) => Ok(value),
+        Ok(Ok(Err(e))) => Err(format!("URL analysis failed: {}", e)),
+        Ok(Err(e)) => Err(format!("URL validation failed: {}", e)),
+        Err(_) => Err(format!("URL validation timed out after {:?}", limit)),
+    }
+}
+
+/// Serves the destination screenshot (or the original one when there was no
+/// redirect) as `image/png`, skipping the base64 round trip.
+fn binary_response(response: &ScreenshotResponse) -> HttpResponse {
+    // The screenshots of an unchanged page were dropped; the caller still has them
+    if response.changed == Some(false) {
+        return HttpResponse::NotModified()
+            .insert_header(("X-Final-Url", response.final_url.as_str()))
+            .insert_header(("X-Screenshot-Status", response.status.as_str()))
+            .finish();
+    }
+    let Some(image_data) = response.final_screenshot.as_ref().or(response.original_screenshot.as_ref()) else {
+        return HttpResponse::NotFound().body(format!(
+            "No screenshot was captured: {}",
+            response.message.as_deref().unwrap_or("unknown reason")
+        ));
     };
+    match BASE64.decode(image_data) {
+        Ok(png) => HttpResponse::Ok()
+            .content_type("image/png")
+            .insert_header(("X-Final-Url", response.final_url.as_str()))
+            .insert_header(("X-Screenshot-Status", response.status.as_str()))
+            .body(png),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Corrupt screenshot data: {}", e)),
+    }
+}
 
-    HttpResponse::Ok().json(HealthStatus {
+async fn health_check(screenshot_taker: web::Data<Arc<dyn ScreenshotBackend>>) -> impl Responder {
+    // Backends without a connection pool have nothing to exhaust
+    let (mut status, active, total) = match screenshot_taker.connection_counts() {
+        None => ("healthy", 0, 0),
+        Some((active, total)) if active < total => ("healthy", active, total),
+        Some((active, total)) if active == total => ("degraded", active, total),
+        Some((active, total)) => ("unhealthy", active, total),
+    };
+    let reachable = screenshot_taker.is_reachable().await;
+    let mut response = if reachable == Some(false) {
+        // Stale pooled sessions can make the counts look fine; the probe wins
+        status = "unhealthy";
+        HttpResponse::ServiceUnavailable()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.json(HealthStatus {
         status: status.to_string(),
         active_connections: active,
         total_connections: total,
+        webdriver_reachable: reachable,
         uptime: Duration::from_secs(0), // TODO: Add uptime tracking
     })
 }
 
+async fn version_handler() -> impl Responder {
+    HttpResponse::Ok().json(VersionInfo::current())
+}
+
+/// Checks the `Authorization: Bearer <token>` header against `admin_token`,
+/// returning the rejection response when the caller is not authorized.
+fn admin_rejection(req: &HttpRequest, config: &ApiConfig) -> Option<HttpResponse> {
+    let Some(expected) = config.admin_token.as_deref() else {
+        return Some(HttpResponse::Forbidden().body("Admin endpoints are disabled."));
+    };
+    let provided = req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    // Compare without short-circuiting so the token can't be guessed by timing
+    let matches = provided.len() == expected.len()
+        && provided.bytes().zip(expected.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0;
+    if matches {
+        None
+    } else {
+        warn!("Rejected unauthorized admin request to {}", req.path());
+        Some(HttpResponse::Unauthorized().body("Invalid admin token."))
+    }
+}
+
+/// Resolves redirect chains only: no browser is involved, so this is far
+/// cheaper than `/screenshot` for bulk triage. Like `/screenshot`, URLs are
+/// anonymized before they are requested. Per-URL failures are reported inline.
+/// With `stream_to_file` the batch runs in the background instead and `202`
+/// returns a job whose progress is polled at `GET /redirects/jobs/{job_id}`.
+/// Batches crawl through the server's shared `Crawler`, so
+/// `max_connections_per_host` holds across concurrent batches and captures.
+async fn redirects_handler(
+    request: web::Json<RedirectBatchRequest>,
+    config: web::Data<ApiConfig>,
+    crawler: web::Data<Crawler>,
+    jobs: web::Data<BatchJobs>,
+) -> HttpResponse {
+    let RedirectBatchRequest { urls, stream_to_file } = request.into_inner();
+    if urls.is_empty() {
+        return HttpResponse::BadRequest().body("No URLs given.");
+    }
+    if stream_to_file {
+        let Some(output_dir) = &config.batch_output_dir else {
+            return HttpResponse::BadRequest().body("Streaming batches to disk is disabled.");
+        };
+        if urls.len() > config.max_streamed_batch {
+            return HttpResponse::BadRequest()
+                .body(format!("At most {} URLs are accepted per streamed request.", config.max_streamed_batch));
+        }
+        let crawler = crawler.get_ref().clone();
+        return match jobs.start(urls, output_dir, config.parser.clone(), crawler, config.redirect_batch_concurrency).await {
+            Ok(status) => HttpResponse::Accepted().json(status),
+            Err(e) => HttpResponse::InternalServerError().body(format!("Failed to create batch output: {}", e)),
+        };
+    }
+    if urls.len() > config.max_redirect_batch {
+        return HttpResponse::BadRequest()
+            .body(format!("At most {} URLs are accepted per request.", config.max_redirect_batch));
+    }
+
+    // Duplicates (after normalization) are crawled once. `first_positions`
+    // holds the position of each distinct URL's first occurrence, and
+    // `distinct` maps every position to its distinct URL.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut first_positions = Vec::new();
+    let distinct: Vec<usize> = urls.iter().enumerate()
+        .map(|(position, url)| {
+            let key = normalize_url(url).unwrap_or_else(|_| url.clone());
+            *seen.entry(key).or_insert_with(|| {
+                first_positions.push(position);
+                first_positions.len() - 1
+            })
+        })
+        .collect();
+    if first_positions.len() < urls.len() {
+        info!("Collapsed {} duplicate URLs in redirect batch", urls.len() - first_positions.len());
+    }
+
+    let parsed: Vec<Result<String>> = first_positions.iter()
+        .map(|&position| ParsedUrl::with_config(&urls[position], &config.parser).map(|parsed| parsed.anonymized_url))
+        .collect();
+    let crawlable: Vec<String> = parsed.iter().filter_map(|url| url.as_ref().ok().cloned()).collect();
+    let mut crawled = crawl_multiple_with_crawler(&crawlable, &crawler, config.redirect_batch_concurrency)
+        .await
+        .into_iter();
+
+    let unique: Vec<RedirectBatchItem> = first_positions.iter().zip(parsed)
+        .map(|(&position, anonymized)| {
+            let url = urls[position].clone();
+            let result = anonymized.and_then(|_| match crawled.next() {
+                Some((_, result)) => result,
+                None => Err(anyhow::anyhow!("Missing crawl result")),
+            });
+            RedirectBatchItem::new(url, result)
+        })
+        .collect();
+
+    let items: Vec<RedirectBatchItem> = urls.into_iter().zip(distinct).enumerate()
+        .map(|(position, (url, index))| {
+            let first = first_positions[index];
+            let mut item = unique[index].clone();
+            item.url = url;
+            item.duplicate_of = (first != position).then_some(first);
+            item
+        })
+        .collect();
+    HttpResponse::Ok().json(items)
+}
+
+async fn warmup_handler(
+    req: HttpRequest,
+    query: web::Query<WarmupQuery>,
+    config: web::Data<ApiConfig>,
+    screenshot_taker: web::Data<Arc<dyn ScreenshotBackend>>,
+) -> HttpResponse {
+    if let Some(rejection) = admin_rejection(&req, &config) {
+        return rejection;
+    }
+
+    let requested = query.n.unwrap_or(MAX_CONNECTIONS).min(MAX_CONNECTIONS);
+    let created = screenshot_taker.warm_up(requested).await;
+    HttpResponse::Ok().json(WarmupResponse {
+        created,
+        total_connections: total_connections(screenshot_taker.as_ref().as_ref()),
+    })
+}
+
+async fn recycle_pool_handler(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    screenshot_taker: web::Data<Arc<dyn ScreenshotBackend>>,
+) -> HttpResponse {
+    if let Some(rejection) = admin_rejection(&req, &config) {
+        return rejection;
+    }
+
+    let (closed, created) = screenshot_taker.recycle_all().await;
+    HttpResponse::Ok().json(RecycleResponse {
+        closed,
+        created,
+        total_connections: total_connections(screenshot_taker.as_ref().as_ref()),
+    })
+}
+
+async fn archive_handler(path: web::Path<String>, config: web::Data<ApiConfig>) -> impl Responder {
+    let Some(archive_dir) = &config.archive_dir else {
+        return HttpResponse::NotFound().body("Archiving is disabled.");
+    };
+    let request_id = path.into_inner();
+    if !is_valid_request_id(&request_id) {
+        return HttpResponse::BadRequest().body("Invalid request id.");
+    }
+    let file_path = Path::new(archive_dir).join(format!("{}.json", request_id));
+    match tokio::fs::read(&file_path).await {
+        Ok(body) => HttpResponse::Ok().content_type("application/json").body(body),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().body(format!("No archived response for {}.", request_id))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to read archived response: {}", e)),
+    }
+}
+
+/// Request ids are generated by `new_request_id`; anything else (in
+/// particular path separators or `..`) never names an archive file.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+async fn archive_response(archive_dir: &str, response: &ScreenshotResponse) -> Result<()> {
+    let file_path = Path::new(archive_dir).join(format!("{}.json", response.request_id));
+    tokio::fs::write(&file_path, serde_json::to_vec(response)?).await
+        .with_context(|| format!("Failed to write {}", file_path.display()))?;
+    debug!("Archived response to {}", file_path.display());
+    Ok(())
+}
+
+fn total_connections(backend: &dyn ScreenshotBackend) -> usize {
+    backend.connection_counts().map_or(0, |(_, total)| total)
+}
+
+/// JSON extractor settings that answer oversized bodies with a plain 413
+/// instead of actix's default error.
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, _req| {
+            let response = match &err {
+                JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                    HttpResponse::PayloadTooLarge()
+                        .body(format!("Request body exceeds the {} byte limit.", limit))
+                }
+                _ => HttpResponse::BadRequest().body(format!("Invalid JSON body: {}", err)),
+            };
+            InternalError::from_response(err, response).into()
+        })
+}
+
 pub async fn start_server(host: &str, port: u16, config: Option<ApiConfig>) -> Result<()> {
-    let config = config.unwrap_or_else(|| ApiConfig {
-        screenshot_dir: "screenshots".to_string(),
-        viewport_width: 1280,
-        viewport_height: 800,
-        headless: true,
-        webdriver_url: None,
-        request_timeout: Duration::from_secs(30),
+    let config = config.unwrap_or_default();
+    let screenshot_taker = Arc::new(ScreenshotTaker::new(config.screenshot_config()).await?);
+    start_server_with_backend(host, port, config, screenshot_taker).await
+}
+
+/// Processes one queued job and sends the result back to its handler. A job
+/// whose client already gave up (its receiver was dropped, e.g. on a client
+/// timeout) is skipped, and one that is abandoned mid-flight is aborted, so
+/// no browser work is spent on responses nobody will read. Completed
+/// analyses are counted in `metrics`.
+async fn worker_task(
+    job: ScreenshotJob,
+    config: &ApiConfig,
+    screenshot_taker: &dyn ScreenshotBackend,
+    crawler: &Crawler,
+    metrics: &Metrics,
+) {
+    let ScreenshotJob { request, parsed_url, timeout: job_timeout, enqueued_at, mut response_tx, tracking } = job;
+    if response_tx.is_closed() {
+        info!("Skipping job for {}: the client is gone", config.loggable(&request.url));
+        return;
+    }
+    tracking.set_processing();
+    let queue_wait = enqueued_at.elapsed();
+    let processing_started = Instant::now();
+    let url = request.url.clone();
+    let request_id = Some(tracking.request_id().to_string());
+    let processing = timeout(job_timeout, process_parsed_request(request, parsed_url, request_id, config, screenshot_taker, crawler));
+    // Dropping `processing` cancels the capture in flight; backends must
+    // give its browser session back when that happens (see `ScreenshotTaker`)
+    let result = tokio::select! {
+        result = processing => match result {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Processing timed out after {:?}", job_timeout)),
+        },
+        _ = response_tx.closed() => {
+            info!("Aborted job for {} after {:?}: the client is gone", config.loggable(&url), processing_started.elapsed());
+            return;
+        }
+    };
+    let processing = processing_started.elapsed();
+    debug!("Job waited {:?} in the queue and processed in {:?}", queue_wait, processing);
+    let result = result.map(|mut response| {
+        metrics.record_hops(response.redirect_chain.len().saturating_sub(1));
+        response.timings.insert(0, OperationTiming {
+            operation: "queue_wait".to_string(),
+            duration_ms: queue_wait.as_millis() as u64,
+        });
+        response.record_timing("processing", processing);
+        response
     });
+    if let (Ok(response), Some(archive_dir)) = (&result, &config.archive_dir) {
+        if let Err(e) = archive_response(archive_dir, response).await {
+            warn!("{:#}", e);
+        }
+    }
+    if response_tx.send(result).is_err() {
+        warn!("Client for {} went away before its response was ready", config.loggable(&url));
+    }
+}
 
-    let screenshot_taker = Arc::new(ScreenshotTaker::new(
-        &config.screenshot_dir,
-        config.webdriver_url.as_deref(),
-        Some((config.viewport_width, config.viewport_height)),
-        config.headless
-    ).await?);
+/// Like `start_server`, but captures through a caller-supplied backend instead
+/// of the default WebDriver `ScreenshotTaker`.
+pub async fn start_server_with_backend(
+    host: &str,
+    port: u16,
+    config: ApiConfig,
+    screenshot_taker: Arc<dyn ScreenshotBackend>,
+) -> Result<()> {
+    let crawler_config = config.crawler_config();
+    let denylist_data = web::Data::new(UrlDenylist::new(&crawler_config.denylist)?);
+    // One crawler for workers and batches alike, so they share its connection
+    // pool, DNS cache and per-host limits
+    let crawler = Arc::new(Crawler::new(crawler_config)?);
+    let crawler_data = web::Data::from(crawler.clone());
 
     // Create the job queue
     let (job_tx, job_rx) = mpsc::channel::<ScreenshotJob>(QUEUE_SIZE);
     let job_tx_data = web::Data::new(job_tx.clone());
     let config_data = web::Data::new(config.clone());
+    let cache_data = web::Data::new(ResponseCache::new(config.response_cache_ttl));
+    if let Some(archive_dir) = &config.archive_dir {
+        std::fs::create_dir_all(archive_dir)
+            .with_context(|| format!("Failed to create archive directory: {}", archive_dir))?;
+    }
+    if let Some(batch_output_dir) = &config.batch_output_dir {
+        std::fs::create_dir_all(batch_output_dir)
+            .with_context(|| format!("Failed to create batch output directory: {}", batch_output_dir))?;
+    }
+    let batch_jobs_data = web::Data::new(BatchJobs::default());
+    let screenshot_jobs_data = web::Data::new(ScreenshotJobs::default());
+    let metrics_data = web::Data::new(Metrics::default());
     let screenshot_taker_data = web::Data::new(screenshot_taker.clone());
+    let max_payload_bytes = config.max_payload_bytes;
 
     // Spawn worker tasks (all share the same receiver)
     let job_rx = Arc::new(tokio::sync::Mutex::new(job_rx));
     for _ in 0..MAX_CONNECTIONS {
         let screenshot_taker = screenshot_taker.clone();
+        let crawler = crawler.clone();
         let job_rx = job_rx.clone();
         let config = config.clone();
+        let metrics = metrics_data.clone();
         tokio::spawn(async move {
             loop {
                 let job_opt = { job_rx.lock().await.recv().await };
                 if let Some(job) = job_opt {
-                    let result = process_request(job.request, &config, screenshot_taker.clone()).await;
-                    let _ = job.response_tx.send(result.map_err(|e| e.to_string()));
+                    worker_task(job, &config, screenshot_taker.as_ref(), &crawler, &metrics).await;
                 } else {
                     break;
                 }
@@ -220,11 +1558,27 @@ pub async fn start_server(host: &str, port: u16, config: Option<ApiConfig>) -> R
     info!("Starting server at {}:{}", host, port);
     HttpServer::new(move || {
         App::new()
+            .app_data(json_config(max_payload_bytes))
+            .app_data(web::PayloadConfig::new(max_payload_bytes))
             .app_data(config_data.clone())
+            .app_data(denylist_data.clone())
+            .app_data(crawler_data.clone())
+            .app_data(cache_data.clone())
+            .app_data(batch_jobs_data.clone())
+            .app_data(screenshot_jobs_data.clone())
+            .app_data(metrics_data.clone())
             .app_data(job_tx_data.clone())
             .app_data(screenshot_taker_data.clone())
             .service(web::resource("/screenshot").route(web::post().to(screenshot_handler)))
+            .service(web::resource("/redirects").route(web::post().to(redirects_handler)))
+            .service(web::resource("/redirects/jobs/{job_id}").route(web::get().to(batch::batch_job_handler)))
+            .service(web::resource("/archive/{request_id}").route(web::get().to(archive_handler)))
             .service(web::resource("/health").route(web::get().to(health_check)))
+            .service(web::resource("/version").route(web::get().to(version_handler)))
+            .service(web::resource("/metrics").route(web::get().to(metrics::metrics_handler)))
+            .service(web::resource("/admin/warmup").route(web::post().to(warmup_handler)))
+            .service(web::resource("/admin/pool/recycle").route(web::post().to(recycle_pool_handler)))
+            .service(web::resource("/admin/jobs").route(web::get().to(jobs::jobs_handler)))
     })
     .bind((host, port))?
     .run()
@@ -234,4 +1588,1029 @@ pub async fn start_server(host: &str, port: u16, config: Option<ApiConfig>) -> R
     screenshot_taker.close().await?;
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http_response, request_path, spawn_delayed_mock_server, spawn_mock_server};
+
+    /// Config for tests against local mock servers, which don't need the
+    /// crawler's politeness delay.
+    fn test_config() -> ApiConfig {
+        ApiConfig {
+            crawler: CrawlerConfig { rate_limit_delay: Duration::ZERO, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    fn test_crawler(config: &ApiConfig) -> Crawler {
+        Crawler::new(config.crawler_config()).unwrap()
+    }
+
+    /// How `TestBackend` answers a capture.
+    #[derive(Clone, Copy)]
+    enum Outcome {
+        /// A tiny static screenshot
+        Success,
+        /// A plain error
+        Failure,
+        /// An error with a failure screenshot attached
+        FailureWithArtifact,
+        /// Nothing within any time a test would wait
+        Hang,
+    }
+
+    /// Screenshot backend double that records the options of every capture
+    /// it is asked for.
+    struct TestBackend {
+        outcome: Outcome,
+        captures: std::sync::Mutex<Vec<CaptureOptions>>,
+        /// Browser slots acquired by captures and not released yet
+        slots_in_use: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    /// A browser slot held by one `TestBackend` capture, released on drop
+    /// like a pooled connection.
+    struct Slot(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Drop for Slot {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    impl TestBackend {
+        fn new(outcome: Outcome) -> Self {
+            Self { outcome, captures: std::sync::Mutex::new(Vec::new()), slots_in_use: Arc::default() }
+        }
+
+        fn slots_in_use(&self) -> usize {
+            self.slots_in_use.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        /// `field` of every recorded capture, in order.
+        fn recorded<T>(&self, field: impl Fn(&CaptureOptions) -> T) -> Vec<T> {
+            self.captures.lock().unwrap().iter().map(field).collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ScreenshotBackend for TestBackend {
+        async fn take_screenshot(&self, _url: &str, _base_name: &str, options: &CaptureOptions) -> Result<crate::screenshot::Screenshot> {
+            self.captures.lock().unwrap().push(options.clone());
+            self.slots_in_use.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let _slot = Slot(self.slots_in_use.clone());
+            match self.outcome {
+                Outcome::Success => {}
+                Outcome::Failure => anyhow::bail!("WebDriver unavailable"),
+                Outcome::FailureWithArtifact => {
+                    return Err(CaptureFailure { error: anyhow::anyhow!("Wait for body timed out"), artifact: BASE64.encode(b"png") }.into());
+                }
+                Outcome::Hang => tokio::time::sleep(Duration::from_secs(60)).await,
+            }
+            Ok(crate::screenshot::Screenshot::new("static.png".to_string(), BASE64.encode(b"png")))
+        }
+
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let request: ScreenshotRequest = serde_json::from_str(r#"{"url": "HTTPS://Example.com:443/a/../b"}"#).unwrap();
+        let same: ScreenshotRequest = serde_json::from_str(r#"{"url": "https://example.com/b", "timeout_secs": 5, "no_cache": true}"#).unwrap();
+        let other: ScreenshotRequest = serde_json::from_str(r#"{"url": "https://example.com/b", "full_page": true}"#).unwrap();
+        let key = request.cache_key().unwrap();
+        assert_eq!(same.cache_key().unwrap(), key);
+        assert_ne!(other.cache_key().unwrap(), key);
+
+        let cache = ResponseCache::new(Some(Duration::from_secs(60)));
+        let mut response = ScreenshotResponse::new("https://example.com/b".to_string());
+        response.status = "partial".to_string();
+        cache.insert(key.clone(), &response);
+        assert!(cache.get(&key).is_none());
+
+        response.status = "success".to_string();
+        cache.insert(key.clone(), &response);
+        let cached = cache.get(&key).unwrap();
+        assert!(cached.from_cache);
+        assert_eq!(cached.request_id, response.request_id);
+
+        let disabled = ResponseCache::new(None);
+        disabled.insert(key.clone(), &response);
+        assert!(disabled.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_failure_artifact_recorded() {
+        let mut response = ScreenshotResponse::new("https://example.com/".to_string());
+        let failure: anyhow::Error = CaptureFailure {
+            error: anyhow::anyhow!("Wait for body timed out"),
+            artifact: "iVBORw0KGgo=".to_string(),
+        }.into();
+        record_screenshot_failure(&mut response, "final", &failure, None);
+        // The original error is still what's reported
+        assert_eq!(response.message.as_deref(), Some("final screenshot failed: Wait for body timed out"));
+        assert_eq!(response.failure_artifacts.len(), 1);
+        assert_eq!(response.failure_artifacts[0].kind, "final");
+
+        record_screenshot_failure(&mut response, "original", &anyhow::anyhow!("no artifact"), None);
+        assert_eq!(response.failure_artifacts.len(), 1);
+    }
+
+    #[test]
+    fn test_screenshot_failure_keeps_enrichment() {
+        let mut response = ScreenshotResponse::new("https://example.com/start".to_string());
+        response.anonymized_url = "https://example.com/start".to_string();
+        response.final_url = "https://example.com/landing".to_string();
+        response.final_screenshot = Some("aW1hZ2U=".to_string());
+
+        record_screenshot_failure(&mut response, "original", &anyhow::anyhow!("WebDriver unavailable"), None);
+
+        assert_eq!(response.status, "partial");
+        assert!(response.original_screenshot.is_none());
+        assert_eq!(response.final_url, "https://example.com/landing");
+        assert!(response.final_screenshot.is_some());
+        assert!(response.message.unwrap().contains("WebDriver unavailable"));
+    }
+
+    #[test]
+    fn test_effective_timeout() {
+        let config = ApiConfig::default();
+        let mut request = ScreenshotRequest {
+            url: "https://example.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(request.effective_timeout(&config), config.request_timeout);
+
+        request.timeout_secs = Some(5);
+        assert_eq!(request.effective_timeout(&config), Duration::from_secs(5));
+
+        request.timeout_secs = Some(10_000);
+        assert_eq!(request.effective_timeout(&config), config.max_request_timeout);
+    }
+
+    #[test]
+    fn test_crawler_config_dns_cache() {
+        assert!(ApiConfig::default().crawler_config().dns_cache.is_none());
+
+        let config = ApiConfig { dns_cache_ttl: Some(Duration::from_secs(60)), ..Default::default() };
+        assert!(config.crawler_config().dns_cache.is_some());
+
+        let config = ApiConfig {
+            crawler: CrawlerConfig { dns_cache: Some(DnsCache::new(Duration::from_secs(5))), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(config.crawler_config().dns_cache.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_post_navigation_delay() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/start" => http_response("302 Found", &[("Location", "/landing")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let config = ApiConfig {
+            max_post_navigation_delay: Duration::from_secs(5),
+            ..test_config()
+        };
+        let crawler = test_crawler(&config);
+        let backend = TestBackend::new(Outcome::Success);
+
+        // Only the destination waits longer, and no longer than the ceiling
+        let request = ScreenshotRequest {
+            post_navigation_delay_ms: Some(60_000),
+            ..ScreenshotRequest::new(format!("http://{}/start", addr))
+        };
+        process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(backend.recorded(|options| (options.kind.clone(), options.post_load_delay)), vec![
+            (Some("original".to_string()), None),
+            (Some("destination".to_string()), Some(Duration::from_secs(5))),
+        ]);
+    }
+
+    #[actix_web::test]
+    async fn test_host_header() {
+        let other = spawn_mock_server(|_| http_response("200 OK", &[], "")).await;
+        let addr = spawn_mock_server(move |request| match request_path(request) {
+            "/start" => http_response("302 Found", &[("Location", "/landing")], ""),
+            "/away" => http_response("302 Found", &[("Location", &format!("http://{}/", other))], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let config = test_config();
+        let crawler = test_crawler(&config);
+
+        // Both captures of a chain on one origin use it
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest {
+            host_header: Some("example.com".to_string()),
+            ..ScreenshotRequest::new(format!("http://{}/start", addr))
+        };
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(backend.recorded(|options| options.host_header.clone()), vec![Some("example.com".to_string()); 2]);
+
+        // A destination on another origin is captured as itself
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest {
+            host_header: Some("example.com".to_string()),
+            ..ScreenshotRequest::new(format!("http://{}/away", addr))
+        };
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(response.redirect_chain.len(), 2);
+        assert_eq!(backend.recorded(|options| options.host_header.clone()), vec![Some("example.com".to_string()), None]);
+    }
+
+    #[actix_web::test]
+    async fn test_non_html_destination_skipped() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/start" => http_response("302 Found", &[("Location", "/report.pdf")], ""),
+            _ => http_response("200 OK", &[("Content-Type", "application/pdf")], ""),
+        }).await;
+        let config = test_config();
+        let crawler = test_crawler(&config);
+
+        // The redirecting page is still captured, only the PDF is not
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert!(response.original_screenshot.is_some());
+        assert!(response.final_screenshot.is_none());
+        assert_eq!(response.message.as_deref(), Some("Destination screenshot skipped: destination serves application/pdf"));
+        assert_eq!(backend.recorded(|options| options.kind.clone()), vec![Some("original".to_string())]);
+
+        // A failed original capture is still reported next to the skip
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &TestBackend::new(Outcome::Failure), &crawler).await.unwrap();
+        assert_eq!(response.status, "partial");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("original screenshot failed: WebDriver unavailable; Destination screenshot skipped: destination serves application/pdf")
+        );
+
+        // Submitting the PDF itself captures nothing
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest::new(format!("http://{}/report.pdf", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(response.status, "skipped");
+        assert!(backend.recorded(|_| ()).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_screenshot_cap() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/start" => http_response("302 Found", &[("Location", "/landing")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let config = ApiConfig {
+            capture_on_failure: true,
+            max_screenshots_per_request: 3,
+            ..test_config()
+        };
+        let crawler = test_crawler(&config);
+
+        // Original and final are both attempted; only one failure screenshot fits
+        let backend = TestBackend::new(Outcome::FailureWithArtifact);
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(backend.recorded(|options| options.skip_failure_capture), vec![false, true]);
+        assert_eq!(response.failure_artifacts.len(), 1);
+        assert_eq!(response.failure_artifacts[0].kind, "original");
+        assert!(response.message.unwrap().ends_with("final failure screenshot skipped: at most 3 screenshots per request"));
+
+        // A cap of one only leaves room for the original
+        let config = ApiConfig { max_screenshots_per_request: 1, ..config };
+        let backend = TestBackend::new(Outcome::FailureWithArtifact);
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(backend.recorded(|options| (options.kind.clone(), options.skip_failure_capture)), vec![(Some("original".to_string()), true)]);
+        assert!(response.failure_artifacts.is_empty());
+
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert_eq!(backend.recorded(|_| ()).len(), 1);
+        assert_eq!(response.status, "success");
+        assert!(response.original_screenshot.is_some());
+        assert!(response.final_screenshot.is_none());
+        assert_eq!(response.message.as_deref(), Some("Destination screenshot skipped: at most 1 screenshots per request"));
+
+        // With no screenshots allowed only the crawl is reported
+        let config = ApiConfig { max_screenshots_per_request: 0, ..config };
+        let backend = TestBackend::new(Outcome::Success);
+        let request = ScreenshotRequest::new(format!("http://{}/start", addr));
+        let response = process_request(request, &config, &backend, &crawler).await.unwrap();
+        assert!(backend.recorded(|_| ()).is_empty());
+        assert_eq!(response.redirect_chain.len(), 2);
+    }
+
+    #[test]
+    fn test_screenshot_content_type_allowlist() {
+        let allowed = ApiConfig::default().screenshot_content_types;
+        assert!(is_screenshot_content_type(Some("text/html"), &allowed));
+        assert!(is_screenshot_content_type(None, &allowed));
+        assert!(!is_screenshot_content_type(Some("application/pdf"), &allowed));
+    }
+
+    #[test]
+    fn test_admin_rejection() {
+        let mut config = ApiConfig::default();
+        let req = actix_web::test::TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer s3cret"))
+            .to_http_request();
+        // Disabled when no token is configured
+        assert!(admin_rejection(&req, &config).is_some());
+
+        config.admin_token = Some("s3cret".to_string());
+        assert!(admin_rejection(&req, &config).is_none());
+
+        config.admin_token = Some("other".to_string());
+        assert!(admin_rejection(&req, &config).is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_custom_backend_health() {
+        let backend: Arc<dyn ScreenshotBackend> = Arc::new(TestBackend::new(Outcome::Success));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(backend))
+                .service(web::resource("/health").route(web::get().to(health_check)))
+        ).await;
+
+        let request = actix_web::test::TestRequest::get().uri("/health").to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["total_connections"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_version() {
+        let app = actix_web::test::init_service(
+            App::new().service(web::resource("/version").route(web::get().to(version_handler)))
+        ).await;
+
+        let request = actix_web::test::TestRequest::get().uri("/version").to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!body["git_commit"].as_str().unwrap().is_empty());
+        assert!(body["build_timestamp"].as_str().unwrap().parse::<DateTime<Utc>>().is_ok());
+    }
+
+    #[test]
+    fn test_captured_at_serialized_as_iso8601() {
+        let mut response = ScreenshotResponse::new("https://example.com/".to_string());
+        response.captured_at = Some("2024-05-14T09:21:07.512Z".parse().unwrap());
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["captured_at"], "2024-05-14T09:21:07.512Z");
+
+        response.captured_at = None;
+        assert!(serde_json::to_value(&response).unwrap()["captured_at"].is_null());
+    }
+
+    #[test]
+    fn test_detect_change() {
+        let encode = |shade: fn(u32) -> u8| {
+            let image = image::RgbImage::from_fn(64, 64, |x, _| image::Rgb([shade(x); 3]));
+            let mut png = Vec::new();
+            image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+            BASE64.encode(png)
+        };
+        let response_with = |image_data: &str| {
+            let mut response = ScreenshotResponse::new("https://example.com/".to_string());
+            response.final_screenshot = Some(image_data.to_string());
+            response
+        };
+        let page = encode(|x| (x * 4) as u8);
+        let redesigned = encode(|x| 255 - (x * 4) as u8);
+
+        let mut first = response_with(&page);
+        detect_change(&mut first, None, 5);
+        let hash = first.screenshot_hash.clone().unwrap();
+        assert_eq!(hash.len(), 16);
+        assert!(first.changed.is_none());
+        assert!(first.final_screenshot.is_some());
+
+        let mut unchanged = response_with(&page);
+        detect_change(&mut unchanged, Some(&hash), 5);
+        assert_eq!(unchanged.changed, Some(false));
+        assert!(unchanged.final_screenshot.is_none());
+
+        let mut changed = response_with(&redesigned);
+        detect_change(&mut changed, Some(&hash), 5);
+        assert_eq!(changed.changed, Some(true));
+        assert!(changed.final_screenshot.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_archive_endpoint() {
+        let archive_dir = std::env::temp_dir().join(format!("archive_test_{}", new_request_id()));
+        std::fs::create_dir_all(&archive_dir).unwrap();
+        let config = ApiConfig {
+            archive_dir: Some(archive_dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let response = ScreenshotResponse::new("https://example.com/".to_string());
+        archive_response(config.archive_dir.as_deref().unwrap(), &response).await.unwrap();
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .service(web::resource("/archive/{request_id}").route(web::get().to(archive_handler)))
+        ).await;
+
+        let request = actix_web::test::TestRequest::get().uri(&format!("/archive/{}", response.request_id)).to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["original_url"], "https://example.com/");
+
+        let missing = actix_web::test::TestRequest::get().uri("/archive/0000000000000000").to_request();
+        assert_eq!(actix_web::test::call_service(&app, missing).await.status(), 404);
+        let traversal = actix_web::test::TestRequest::get().uri("/archive/..%2F..%2Fetc%2Fpasswd").to_request();
+        assert_eq!(actix_web::test::call_service(&app, traversal).await.status(), 400);
+
+        std::fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_redirects_batch() {
+        let config = ApiConfig {
+            max_redirect_batch: 2,
+            denylist: vec!["blocked.test".to_string()],
+            ..Default::default()
+        };
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_crawler(&config)))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(BatchJobs::default()))
+                .service(web::resource("/redirects").route(web::post().to(redirects_handler)))
+        ).await;
+
+        let request = actix_web::test::TestRequest::post().uri("/redirects")
+            .set_json(serde_json::json!({ "urls": ["not a url", "https://blocked.test/x"] }))
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        let items = body.as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["url"], "not a url");
+        assert!(items[0]["error"].is_string());
+        assert!(items[1]["error"].as_str().unwrap().contains("blocked.test"));
+        assert!(items[1]["final_url"].is_null());
+
+        let too_many = actix_web::test::TestRequest::post().uri("/redirects")
+            .set_json(serde_json::json!({ "urls": ["https://a.test", "https://b.test", "https://c.test"] }))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, too_many).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_request_max_hops() {
+        // /1 -> /2 -> /3 -> ... without end
+        let addr = spawn_mock_server(|request| {
+            let next = request_path(request).trim_start_matches('/').parse::<u32>().unwrap_or(0) + 1;
+            http_response("302 Found", &[("Location", &format!("/{}", next))], "")
+        }).await;
+        let config = ApiConfig {
+            max_request_hops: 5,
+            ..test_config()
+        };
+        let crawler = test_crawler(&config);
+        let url = format!("http://{}/1", addr);
+
+        let request = ScreenshotRequest { max_hops: Some(2), ..ScreenshotRequest::new(url.clone()) };
+        let response = process_request(request, &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.redirect_chain.len(), 3);
+        assert_eq!(response.final_url, format!("http://{}/3", addr));
+
+        // Clamped to the server ceiling
+        let request = ScreenshotRequest { max_hops: Some(100), ..ScreenshotRequest::new(url.clone()) };
+        let response = process_request(request, &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.redirect_chain.len(), 6);
+
+        let response = process_request(ScreenshotRequest::new(url), &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.redirect_chain.len(), config.crawler.max_hops + 1);
+    }
+
+    #[actix_web::test]
+    async fn test_redirects_disabled() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/start" => http_response("302 Found", &[("Location", "/landing")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let config = test_config();
+        let crawler = test_crawler(&config);
+        let start = format!("http://{}/start", addr);
+        let landing = format!("http://{}/landing", addr);
+
+        // Following disabled: the single-entry chain says so
+        let request = ScreenshotRequest { follow_redirects: Some(false), ..ScreenshotRequest::new(start.clone()) };
+        let response = process_request(request, &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.redirect_chain.len(), 1);
+        assert_eq!(response.final_url, start);
+        assert_eq!(response.termination, Some(TerminationReason::RedirectsDisabled));
+
+        // A URL that really doesn't redirect, with or without following
+        for follow_redirects in [Some(false), None] {
+            let request = ScreenshotRequest { follow_redirects, ..ScreenshotRequest::new(landing.clone()) };
+            let response = process_request(request, &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+            assert_eq!(response.redirect_chain.len(), 1);
+            assert_eq!(response.termination, Some(TerminationReason::Completed));
+        }
+
+        let response = process_request(ScreenshotRequest::new(start), &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.final_url, landing);
+        assert_eq!(response.termination, Some(TerminationReason::Completed));
+    }
+
+    #[actix_web::test]
+    async fn test_open_redirect_suspected() {
+        let addr = spawn_mock_server(|request| match request_path(request).strip_prefix("/login?next=") {
+            Some(target) => http_response("302 Found", &[("Location", target)], ""),
+            None => http_response("200 OK", &[], ""),
+        }).await;
+        let mut config = test_config();
+        config.crawler.host_overrides = vec![("evil.test".to_string(), addr), ("site.test".to_string(), addr)];
+        let crawler = test_crawler(&config);
+
+        let port = addr.port();
+        let open = format!("http://site.test:{}/login?next=http://evil.test:{}/landing", port, port);
+        let response = process_request(ScreenshotRequest::new(open), &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.final_url, format!("http://evil.test:{}/landing", port));
+        assert!(response.open_redirect_suspected);
+        assert_eq!(response.domain_sources["evil.test"], vec![DomainSource::Query, DomainSource::Redirect]);
+
+        // Redirecting within the site is not an open redirect
+        let internal = format!("http://site.test:{}/login?next=http://site.test:{}/home", port, port);
+        let response = process_request(ScreenshotRequest::new(internal), &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert!(!response.open_redirect_suspected);
+
+        // Nor is a parameter the chain never followed
+        assert!(!open_redirect_suspected(
+            "https://site.test/page?ref=https://evil.test/",
+            &["https://evil.test/".to_string()],
+            &["https://site.test/page?ref=https://evil.test/".to_string()],
+        ));
+    }
+
+    #[test]
+    fn test_domain_sources() {
+        let sources = domain_sources(
+            "https://site.test/login?next=https://evil.test/x&img=https://cdn.test/a.png",
+            &["https://evil.test/x".to_string(), "https://cdn.test/a.png".to_string()],
+            &[
+                "https://site.test/login?next=https://evil.test/x&img=https://cdn.test/a.png".to_string(),
+                "https://site.test/redirect".to_string(),
+                "https://evil.test/x".to_string(),
+            ],
+        );
+        assert_eq!(sources.len(), 3);
+        assert_eq!(sources["site.test"], vec![DomainSource::Host, DomainSource::Redirect]);
+        assert_eq!(sources["evil.test"], vec![DomainSource::Query, DomainSource::Redirect]);
+        assert_eq!(sources["cdn.test"], vec![DomainSource::Query]);
+        assert_eq!(serde_json::to_value(&sources["evil.test"]).unwrap(), serde_json::json!(["query", "redirect"]));
+    }
+
+    #[actix_web::test]
+    async fn test_exclude_image_data() {
+        let addr = spawn_mock_server(|_| http_response("200 OK", &[], "")).await;
+        let config = test_config();
+        let crawler = test_crawler(&config);
+        let url = format!("http://{}/", addr);
+
+        let response = process_request(ScreenshotRequest::new(url.clone()), &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert!(response.original_screenshot.is_some());
+        assert_eq!(response.original_screenshot_path.as_deref(), Some("static.png"));
+
+        let request = ScreenshotRequest { include_image_data: Some(false), ..ScreenshotRequest::new(url) };
+        let response = process_request(request, &config, &TestBackend::new(Outcome::Success), &crawler).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert!(response.original_screenshot.is_none());
+        assert_eq!(response.original_screenshot_path.as_deref(), Some("static.png"));
+    }
+
+    #[actix_web::test]
+    async fn test_worker_skips_abandoned_jobs() {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let served = requests.clone();
+        let addr = spawn_mock_server(move |_| {
+            served.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            http_response("200 OK", &[], "")
+        }).await;
+        let config = test_config();
+        let crawler = test_crawler(&config);
+        let backend = TestBackend::new(Outcome::Hang);
+        let metrics = Metrics::default();
+        let jobs = ScreenshotJobs::default();
+        let job = |response_tx| ScreenshotJob {
+            request: ScreenshotRequest::new(format!("http://{}/", addr)),
+            parsed_url: None,
+            timeout: Duration::from_secs(120),
+            enqueued_at: Instant::now(),
+            response_tx,
+            tracking: jobs.track(new_request_id(), format!("http://{}/", addr)),
+        };
+
+        // The client gave up before the job was picked up: nothing is done
+        let (response_tx, response_rx) = oneshot::channel();
+        drop(response_rx);
+        worker_task(job(response_tx), &config, &backend, &crawler, &metrics).await;
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert!(backend.recorded(|_| ()).is_empty());
+
+        // The client gives up mid-capture: the job is aborted, not run to the end
+        let (response_tx, response_rx) = oneshot::channel();
+        let watched = jobs.clone();
+        let listed = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let listed = watched.list(10);
+            drop(response_rx);
+            listed
+        });
+        let started = Instant::now();
+        worker_task(job(response_tx), &config, &backend, &crawler, &metrics).await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let listed = listed.await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].state, ScreenshotJobState::Processing);
+        // Listed only while it runs
+        assert!(jobs.list(10).is_empty());
+        assert_eq!(backend.recorded(|_| ()).len(), 1);
+        // The aborted capture gave its browser slot back
+        assert_eq!(backend.slots_in_use(), 0);
+        // Neither job completed, so neither is counted
+        assert!(metrics.render().contains("screenshot_api_redirect_hops_count 0\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_redirects_batch_duplicates() {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let served = requests.clone();
+        let addr = spawn_mock_server(move |_| {
+            served.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            http_response("200 OK", &[], "")
+        }).await;
+
+        let config = test_config();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_crawler(&config)))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(BatchJobs::default()))
+                .service(web::resource("/redirects").route(web::post().to(redirects_handler)))
+        ).await;
+
+        // Equal after normalization (case of scheme and host)
+        let url = format!("http://{}/landing", addr);
+        let urls = [url.clone(), url.replace("http://", "HTTP://"), url.clone()];
+        let request = actix_web::test::TestRequest::post().uri("/redirects")
+            .set_json(serde_json::json!({ "urls": urls }))
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        let items = body.as_array().unwrap();
+
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(items.len(), 3);
+        for (item, submitted) in items.iter().zip(&urls) {
+            assert_eq!(item["url"], submitted.as_str());
+            assert_eq!(item["final_url"], url.as_str());
+        }
+        assert!(items[0]["duplicate_of"].is_null());
+        assert_eq!(items[1]["duplicate_of"], 0);
+        assert_eq!(items[2]["duplicate_of"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_batches_share_host_limit() {
+        let delay = Duration::from_millis(100);
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let times = served.clone();
+        let addr = spawn_delayed_mock_server(delay, move |_| {
+            times.lock().unwrap().push(Instant::now());
+            http_response("200 OK", &[], "")
+        }).await;
+        let mut config = test_config();
+        config.crawler.max_connections_per_host = Some(1);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_crawler(&config)))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(BatchJobs::default()))
+                .service(web::resource("/redirects").route(web::post().to(redirects_handler)))
+        ).await;
+
+        let batch = |paths: [&str; 2]| actix_web::test::TestRequest::post().uri("/redirects")
+            .set_json(serde_json::json!({ "urls": paths.map(|path| format!("http://{}/{}", addr, path)) }))
+            .to_request();
+        let (first, second) = tokio::join!(
+            actix_web::test::call_service(&app, batch(["a", "b"])),
+            actix_web::test::call_service(&app, batch(["c", "d"])),
+        );
+        assert!(first.status().is_success() && second.status().is_success());
+
+        // Two batches, one host: still one request at a time
+        let times = served.lock().unwrap().clone();
+        assert_eq!(times.len(), 4);
+        assert!(times.windows(2).all(|pair| pair[1].duration_since(pair[0]) >= delay - Duration::from_millis(10)));
+    }
+
+    #[actix_web::test]
+    async fn test_redirects_batch_streamed_to_file() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("302 Found", &[("Location", "/landing")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let output_dir = std::env::temp_dir().join(format!("batch_test_{}", new_request_id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let config = ApiConfig {
+            batch_output_dir: Some(output_dir.to_string_lossy().into_owned()),
+            redirect_batch_concurrency: 1,
+            ..test_config()
+        };
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_crawler(&config)))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(BatchJobs::default()))
+                .service(web::resource("/redirects").route(web::post().to(redirects_handler)))
+                .service(web::resource("/redirects/jobs/{job_id}").route(web::get().to(batch::batch_job_handler)))
+        ).await;
+
+        let urls = [format!("http://{}/", addr), "not a url".to_string(), format!("http://{}/other", addr)];
+        let request = actix_web::test::TestRequest::post().uri("/redirects")
+            .set_json(serde_json::json!({ "urls": urls, "stream_to_file": true }))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status(), 202);
+        let job: serde_json::Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(job["total"], 3);
+
+        let uri = format!("/redirects/jobs/{}", job["job_id"].as_str().unwrap());
+        let mut status = serde_json::Value::Null;
+        for _ in 0..100 {
+            let request = actix_web::test::TestRequest::get().uri(&uri).to_request();
+            status = actix_web::test::call_and_read_body_json(&app, request).await;
+            if status["state"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(status["state"], "completed");
+        assert_eq!(status["completed"], 3);
+        assert_eq!(status["failed"], 1);
+
+        let output = std::fs::read_to_string(status["output_path"].as_str().unwrap()).unwrap();
+        let mut items: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        items.sort_by_key(|item| item["position"].as_u64());
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["final_url"], format!("http://{}/landing", addr));
+        assert!(items[1]["error"].is_string());
+        assert_eq!(items[2]["url"], urls[2].as_str());
+
+        assert!(status["finished_at"].is_string());
+        assert!(status["elapsed_ms"].is_u64());
+
+        let unknown = actix_web::test::TestRequest::get().uri("/redirects/jobs/0123456789abcdef").to_request();
+        assert_eq!(actix_web::test::call_service(&app, unknown).await.status(), 404);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_list_jobs() {
+        let addr = spawn_mock_server(|_| http_response("200 OK", &[], "")).await;
+        let output_dir = std::env::temp_dir().join(format!("jobs_test_{}", new_request_id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let jobs = BatchJobs::default();
+        let crawler = test_crawler(&test_config());
+        for _ in 0..3 {
+            let urls = vec![format!("http://{}/", addr)];
+            jobs.start(urls, output_dir.to_str().unwrap(), ParserConfig::default(), crawler.clone(), 1).await.unwrap();
+        }
+        let screenshot_jobs = ScreenshotJobs::default();
+        let queued = screenshot_jobs.track(new_request_id(), "https://example.com/reset?token=***".to_string());
+        let processing = screenshot_jobs.track(new_request_id(), "https://example.com/".to_string());
+        processing.set_processing();
+        let config = ApiConfig { admin_token: Some("s3cret".to_string()), ..Default::default() };
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(jobs))
+                .app_data(web::Data::new(screenshot_jobs))
+                .service(web::resource("/admin/jobs").route(web::get().to(jobs::jobs_handler)))
+        ).await;
+
+        let anonymous = actix_web::test::TestRequest::get().uri("/admin/jobs").to_request();
+        assert_eq!(actix_web::test::call_service(&app, anonymous).await.status(), 401);
+
+        let request = actix_web::test::TestRequest::get().uri("/admin/jobs?limit=2")
+            .insert_header((AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        let listed = body["batch_jobs"].as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0]["started_at"].as_str() >= listed[1]["started_at"].as_str());
+        assert_eq!(listed[0]["total"], 1);
+
+        let listed = body["screenshot_jobs"].as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        let listed_job = |job: &TrackedJob| listed.iter().find(|listed| listed["request_id"] == job.request_id()).unwrap();
+        assert_eq!(listed_job(&processing)["state"], "processing");
+        assert!(listed_job(&processing)["started_at"].is_string());
+        assert_eq!(listed_job(&queued)["state"], "queued");
+        assert_eq!(listed_job(&queued)["url"], "https://example.com/reset?token=***");
+        assert!(listed_job(&queued)["elapsed_ms"].is_u64());
+
+        // Finished jobs drop off the list
+        drop(queued);
+        let request = actix_web::test::TestRequest::get().uri("/admin/jobs")
+            .insert_header((AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["screenshot_jobs"].as_array().unwrap().len(), 1);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_running_batch_jobs_not_evicted() {
+        let addr = spawn_delayed_mock_server(Duration::from_millis(300), |_| http_response("200 OK", &[], "")).await;
+        let output_dir = std::env::temp_dir().join(format!("evict_test_{}", new_request_id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        // Room for a single finished job
+        let jobs = BatchJobs::with_capacity(1);
+        let crawler = test_crawler(&test_config());
+        let mut job_ids = Vec::new();
+        for _ in 0..3 {
+            let urls = vec![format!("http://{}/", addr)];
+            let status = jobs.start(urls, output_dir.to_str().unwrap(), ParserConfig::default(), crawler.clone(), 1).await.unwrap();
+            job_ids.push(status.job_id);
+        }
+        assert_eq!(jobs.list(10).len(), 3);
+        assert!(job_ids.iter().all(|job_id| jobs.status(job_id).unwrap().state == BatchJobState::Running));
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_rejection_reasons() {
+        let (job_tx, _job_rx) = mpsc::channel::<ScreenshotJob>(1);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(ApiConfig::default()))
+                .app_data(web::Data::new(UrlDenylist::new(&[]).unwrap()))
+                .app_data(web::Data::new(ResponseCache::new(None)))
+                .app_data(web::Data::new(job_tx))
+                .app_data(web::Data::new(ScreenshotJobs::default()))
+                .service(web::resource("/screenshot").route(web::post().to(screenshot_handler)))
+        ).await;
+
+        let long_url = format!("https://example.com/{}", "a".repeat(3000));
+        let cases = [
+            ("", "Invalid URL: URL cannot be empty"),
+            (long_url.as_str(), "Invalid URL: URL too long"),
+            ("ftp://example.com/file", "Invalid URL: Unsupported scheme 'ftp'"),
+            ("https://exa mple.com/", "Invalid URL: Malformed URL"),
+        ];
+        for (url, expected) in cases {
+            let request = actix_web::test::TestRequest::post().uri("/screenshot")
+                .set_json(serde_json::json!({ "url": url }))
+                .to_request();
+            let response = actix_web::test::call_service(&app, request).await;
+            assert_eq!(response.status(), 400);
+            let body = actix_web::test::read_body(response).await;
+            assert!(String::from_utf8_lossy(&body).starts_with(expected), "{}", url);
+        }
+
+        // `+` is accepted by `from_str_radix`, but isn't a hex digit
+        for previous_hash in ["+0123456789abcde", "0123456789abcdeg", "0123456789abcdef0"] {
+            let request = actix_web::test::TestRequest::post().uri("/screenshot")
+                .set_json(serde_json::json!({ "url": "https://example.com/", "previous_hash": previous_hash }))
+                .to_request();
+            let response = actix_web::test::call_service(&app, request).await;
+            assert_eq!(response.status(), 400, "{}", previous_hash);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validation_timeout() {
+        assert!(run_validation(Duration::from_secs(1), || Ok(())).await.is_ok());
+
+        let err = run_validation(Duration::from_secs(1), || -> Result<()> { anyhow::bail!("URL cannot be empty") }).await.unwrap_err();
+        assert!(err.contains("URL cannot be empty"));
+
+        let err = run_validation(Duration::from_millis(50), || {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(())
+        }).await.unwrap_err();
+        assert!(err.contains("timed out"));
+    }
+
+    #[test]
+    fn test_job_error_response() {
+        let denied: anyhow::Error = DeniedUrl {
+            url: "http://admin.internal/".to_string(),
+            pattern: "admin.internal".to_string(),
+        }.into();
+        assert_eq!(job_error_response(&denied.context("Crawl failed")).status(), 403);
+        assert_eq!(job_error_response(&anyhow::anyhow!("WebDriver unavailable")).status(), 500);
+    }
+
+    #[test]
+    fn test_siem_summary() {
+        let mut response = ScreenshotResponse::new("https://example.com/start".to_string());
+        response.final_url = "http://example.net/landing".to_string();
+        response.status = "success".to_string();
+        response.redirect_chain = ["https://example.com/start", "https://example.com/next", "http://example.net/landing"]
+            .iter()
+            .map(|url| RedirectHop { url: url.to_string(), status: Some(302), resolved_ips: Vec::new(), body: None })
+            .collect();
+        response.summary = Some(AnalysisSummary::new(&response));
+
+        let summary = siem_summary(&response);
+        assert_eq!(summary["event"], "url_analysis_summary");
+        assert_eq!(summary["hop_count"], 2);
+        assert_eq!(summary["identifier_count"], 0);
+        assert_eq!(summary["final_url_https"], false);
+        assert_eq!(summary["protocol_downgrade"], true);
+        assert_eq!(summary["risk"], "medium");
+        // A single line, so line-oriented SIEM shippers keep it intact
+        assert!(!summary.to_string().contains('\n'));
+    }
+
+    #[test]
+    fn test_analysis_summary() {
+        let hops = |urls: &[&str]| urls.iter()
+            .map(|url| RedirectHop { url: url.to_string(), status: Some(302), resolved_ips: Vec::new(), body: None })
+            .collect::<Vec<_>>();
+
+        let mut response = ScreenshotResponse::new("https://example.com/".to_string());
+        response.final_url = "https://example.com/".to_string();
+        response.redirect_chain = hops(&["https://example.com/"]);
+        let summary = AnalysisSummary::new(&response);
+        assert_eq!(summary.redirect_count, 0);
+        assert_eq!(summary.unique_domain_count, 1);
+        assert_eq!(summary.risk, RiskLevel::Low);
+
+        response.final_url = "https://paypa1.com/login".to_string();
+        response.redirect_chain = hops(&["https://example.com/", "https://t.example.net/r", "https://paypa1.com/login"]);
+        response.suspected_lookalikes = vec!["paypa1.com".to_string()];
+        response.identifiers.push(Identifier { value: "x".to_string(), decoded_value: None, anonymized_value: None });
+        let summary = AnalysisSummary::new(&response);
+        assert_eq!(summary.redirect_count, 2);
+        assert_eq!(summary.unique_domain_count, 3);
+        assert!(summary.sensitive_data_found && summary.lookalike_found && !summary.protocol_downgrade);
+        assert_eq!(summary.risk, RiskLevel::High);
+    }
+
+    #[actix_web::test]
+    async fn test_binary_response() {
+        let mut response = ScreenshotResponse::new("https://example.com/start".to_string());
+        response.final_url = "https://example.com/landing".to_string();
+        response.status = "success".to_string();
+        response.original_screenshot = Some(BASE64.encode(b"original"));
+        response.final_screenshot = Some(BASE64.encode(b"final"));
+
+        let http = binary_response(&response);
+        assert_eq!(http.status(), 200);
+        assert_eq!(http.headers().get("content-type").unwrap(), "image/png");
+        assert_eq!(http.headers().get("x-final-url").unwrap(), "https://example.com/landing");
+        let body = actix_web::body::to_bytes(http.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"final");
+
+        response.final_screenshot = None;
+        let body = actix_web::body::to_bytes(binary_response(&response).into_body()).await.unwrap();
+        assert_eq!(&body[..], b"original");
+
+        response.original_screenshot = None;
+        assert_eq!(binary_response(&response).status(), 404);
+
+        // Unchanged since `previous_hash`: nothing to send, but not an error either
+        response.changed = Some(false);
+        let http = binary_response(&response);
+        assert_eq!(http.status(), 304);
+        assert_eq!(http.headers().get("x-final-url").unwrap(), "https://example.com/landing");
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_body_rejected() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(json_config(64))
+                .route("/echo", web::post().to(|body: web::Json<ScreenshotRequest>| async move {
+                    HttpResponse::Ok().json(body.into_inner())
+                }))
+        ).await;
+
+        let small = actix_web::test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({ "url": "https://example.com" }))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, small).await.status(), 200);
+
+        let large = actix_web::test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({ "url": format!("https://example.com/{}", "a".repeat(200)) }))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, large).await.status(), 413);
+    }
+}
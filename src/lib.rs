@@ -0,0 +1,15 @@
+//! Redirect-chain crawling, URL anonymization and screenshotting for
+//! suspicious links. The HTTP server in `main.rs` is a thin wrapper around
+//! this library; embed the pipeline directly with [`analyze_url`].
+
+pub mod api;
+pub mod screenshot;
+pub mod url_crawler;
+pub mod url_parser;
+pub mod utils;
+
+#[cfg(test)]
+mod test_support;
+
+pub use api::{analyze_url, ApiConfig, ScreenshotRequest, ScreenshotResponse};
+pub use screenshot::{ScreenshotBackend, ScreenshotTaker};
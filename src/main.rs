@@ -1,12 +1,6 @@
-mod api;
-mod screenshot;
-mod url_crawler;
-mod url_parser;
-mod utils;
-
 use anyhow::Result;
-use crate::api::{ApiConfig, start_server};
-use crate::utils::logger::init_logger;
+use screenshot_api::api::{ApiConfig, start_server};
+use screenshot_api::utils::logger::init_logger;
 use std::time::Duration;
 
 #[actix_web::main]
@@ -22,6 +16,7 @@ async fn main() -> Result<()> {
         headless: true,
         webdriver_url: None,
         request_timeout: Duration::from_secs(30),
+        ..Default::default()
     };
 
     // Start server
@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use fantoccini::wd::WebDriverCompatibleCommand;
+use fantoccini::Client;
+use serde_json::{json, Value};
+
+/// A Chrome DevTools Protocol command sent through ChromeDriver's
+/// `goog/cdp/execute` extension endpoint.
+#[derive(Debug)]
+struct CdpCommand {
+    method: String,
+    params: Value,
+}
+
+impl WebDriverCompatibleCommand for CdpCommand {
+    fn endpoint(&self, base_url: &url::Url, session_id: Option<&str>) -> Result<url::Url, url::ParseError> {
+        base_url.join(&format!("session/{}/goog/cdp/execute", session_id.unwrap_or_default()))
+    }
+
+    fn method_and_body(&self, _request_url: &url::Url) -> (http::Method, Option<String>) {
+        let body = json!({ "cmd": self.method, "params": self.params });
+        (http::Method::POST, Some(body.to_string()))
+    }
+}
+
+/// Runs a CDP method on the client's browser and returns its result object.
+/// Fails on WebDriver backends that don't support the CDP extension.
+pub async fn execute(client: &Client, method: &str, params: Value) -> Result<Value> {
+    client.issue_cmd(CdpCommand { method: method.to_string(), params })
+        .await
+        .with_context(|| format!("CDP command {} failed", method))
+}
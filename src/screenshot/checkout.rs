@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use fantoccini::Client;
+use log::error;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// A browser session that can be pooled.
+#[async_trait]
+pub trait Connection: Send + 'static {
+    /// Ends the session, logging rather than returning failures.
+    async fn close(self);
+}
+
+#[async_trait]
+impl Connection for Client {
+    async fn close(self) {
+        if let Err(e) = Client::close(self).await {
+            error!("Failed to close WebDriver client: {}", e);
+        }
+    }
+}
+
+/// A connection taken out of the pool for one capture, counted in `active`
+/// until it is handed back with `release` or `discard`.
+///
+/// Captures are cancelled by dropping their future, e.g. when the client that
+/// asked for them goes away. A checkout dropped that way holds a session in an
+/// unknown state, so it is closed in the background and both counters are
+/// corrected, instead of leaking the session and skewing the pool's scaling.
+pub struct Checkout<C: Connection> {
+    connection: Option<C>,
+    pool: Arc<Mutex<VecDeque<C>>>,
+    active: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl<C: Connection> Checkout<C> {
+    pub fn new(connection: C, pool: Arc<Mutex<VecDeque<C>>>, active: Arc<AtomicUsize>, total: Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Self { connection: Some(connection), pool, active, total }
+    }
+
+    /// A connection that belongs to no pool, for a single capture; `discard`
+    /// it when done (dropping it closes it too).
+    pub fn unpooled(connection: C) -> Self {
+        Self::new(connection, Arc::default(), Arc::default(), Arc::new(AtomicUsize::new(1)))
+    }
+
+    pub fn connection(&self) -> &C {
+        self.connection.as_ref().expect("connection present until released")
+    }
+
+    /// Puts the connection back for the next capture.
+    pub async fn release(mut self) {
+        // Taken only once the lock is held, so cancelling the wait still
+        // leaves the connection to `drop`
+        let mut pool = self.pool.lock().await;
+        if let Some(connection) = self.connection.take() {
+            pool.push_back(connection);
+            self.active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Closes the connection, e.g. because a failed capture may have left it
+    /// in a bad state.
+    pub async fn discard(mut self) {
+        if let Some(connection) = self.take_and_uncount() {
+            connection.close().await;
+        }
+    }
+
+    fn take_and_uncount(&mut self) -> Option<C> {
+        let connection = self.connection.take()?;
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        self.total.fetch_sub(1, Ordering::SeqCst);
+        Some(connection)
+    }
+}
+
+impl<C: Connection> Drop for Checkout<C> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.take_and_uncount() {
+            // Without a runtime (shutdown) the session is simply dropped
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(connection.close());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Counts how many sessions were closed.
+    struct FakeConnection(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Connection for FakeConnection {
+        async fn close(self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkout_cancelled_mid_capture() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(Mutex::new(VecDeque::new()));
+        let active = Arc::new(AtomicUsize::new(0));
+        let total = Arc::new(AtomicUsize::new(2));
+        let checkout = |connection| Checkout::new(connection, pool.clone(), active.clone(), total.clone());
+
+        let released = checkout(FakeConnection(closed.clone()));
+        assert_eq!(active.load(Ordering::SeqCst), 1);
+        released.release().await;
+        assert_eq!(pool.lock().await.len(), 1);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+        assert_eq!(total.load(Ordering::SeqCst), 2);
+
+        // The capture holding it is abandoned halfway
+        let abandoned = checkout(FakeConnection(closed.clone()));
+        let capture = async move {
+            let _checkout = abandoned;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(50), capture).await.is_err());
+        tokio::task::yield_now().await;
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+        assert_eq!(total.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.lock().await.len(), 1);
+
+        checkout(FakeConnection(closed.clone())).discard().await;
+        assert_eq!(closed.load(Ordering::SeqCst), 2);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+        assert_eq!(total.load(Ordering::SeqCst), 0);
+
+        // Sessions outside the pool are closed whichever way they end
+        drop(Checkout::unpooled(FakeConnection(closed.clone())));
+        tokio::task::yield_now().await;
+        assert_eq!(closed.load(Ordering::SeqCst), 3);
+        Checkout::unpooled(FakeConnection(closed.clone())).discard().await;
+        assert_eq!(closed.load(Ordering::SeqCst), 4);
+        assert_eq!(pool.lock().await.len(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive connection failures after which an endpoint is set aside.
+const FAILURES_BEFORE_UNHEALTHY: u32 = 3;
+/// How long an unhealthy endpoint is only tried as a last resort.
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_healthy(&self) -> bool {
+        !matches!(self.unhealthy_until, Some(until) if Instant::now() < until)
+    }
+}
+
+/// WebDriver endpoints in order of preference: the primary followed by warm
+/// standbys. New sessions go to the first healthy endpoint; one that keeps
+/// refusing connections is skipped for a while, so a dead browser backend
+/// fails over to the next instead of failing every capture.
+#[derive(Debug)]
+pub struct WebDriverEndpoints {
+    urls: Vec<String>,
+    health: Mutex<Vec<EndpointHealth>>,
+}
+
+impl WebDriverEndpoints {
+    pub fn new(urls: Vec<String>) -> Self {
+        let health = urls.iter().map(|_| EndpointHealth::default()).collect();
+        Self { urls, health: Mutex::new(health) }
+    }
+
+    /// The endpoint new sessions would currently go to.
+    pub fn preferred(&self) -> Option<&str> {
+        self.candidates().first().map(|&index| self.urls[index].as_str())
+    }
+
+    /// Indexes of healthy endpoints in configured order, then the unhealthy
+    /// ones, which are still worth a try when nothing else works.
+    fn candidates(&self) -> Vec<usize> {
+        let health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) = (0..self.urls.len())
+            .partition(|&index| health[index].is_healthy());
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// Runs `connect` against each candidate endpoint until one succeeds,
+    /// recording the outcome for every endpoint tried.
+    pub async fn connect<T, F, Fut>(&self, mut connect: F) -> Result<T>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+        for index in self.candidates() {
+            match connect(self.urls[index].clone()).await {
+                Ok(client) => {
+                    self.record(index, true);
+                    return Ok(client);
+                }
+                Err(e) => {
+                    warn!("WebDriver endpoint {} failed: {:#}", index + 1, e);
+                    self.record(index, false);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No WebDriver endpoint configured")))
+    }
+
+    fn record(&self, index: usize, success: bool) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let endpoint = &mut health[index];
+        if success {
+            if endpoint.unhealthy_until.is_some() {
+                info!("WebDriver endpoint {} is healthy again", index + 1);
+            }
+            *endpoint = EndpointHealth::default();
+            return;
+        }
+        endpoint.consecutive_failures += 1;
+        if endpoint.consecutive_failures >= FAILURES_BEFORE_UNHEALTHY {
+            if endpoint.is_healthy() {
+                warn!("Marking WebDriver endpoint {} unhealthy for {:?}", index + 1, UNHEALTHY_COOLDOWN);
+            }
+            endpoint.unhealthy_until = Some(Instant::now() + UNHEALTHY_COOLDOWN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: &str = "http://127.0.0.1:1";
+    const STANDBY: &str = "http://standby:4444";
+
+    /// Fails like a WebDriver that refuses connections on `PRIMARY`.
+    async fn connect(url: String, attempts: &Mutex<Vec<String>>) -> Result<String> {
+        attempts.lock().unwrap().push(url.clone());
+        if url == PRIMARY {
+            anyhow::bail!("Connection refused");
+        }
+        Ok(format!("session on {}", url))
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_to_standby() {
+        let endpoints = WebDriverEndpoints::new(vec![PRIMARY.to_string(), STANDBY.to_string()]);
+        let attempts = Mutex::new(Vec::new());
+
+        let session = endpoints.connect(|url| connect(url, &attempts)).await.unwrap();
+        assert_eq!(session, "session on http://standby:4444");
+        assert_eq!(*attempts.lock().unwrap(), vec![PRIMARY, STANDBY]);
+
+        // Once the primary has failed repeatedly it is no longer tried first
+        for _ in 1..FAILURES_BEFORE_UNHEALTHY {
+            endpoints.connect(|url| connect(url, &attempts)).await.unwrap();
+        }
+        assert_eq!(endpoints.preferred(), Some(STANDBY));
+        attempts.lock().unwrap().clear();
+        endpoints.connect(|url| connect(url, &attempts)).await.unwrap();
+        assert_eq!(*attempts.lock().unwrap(), vec![STANDBY]);
+    }
+
+    #[tokio::test]
+    async fn test_all_endpoints_down() {
+        let endpoints = WebDriverEndpoints::new(vec![PRIMARY.to_string()]);
+        let attempts = Mutex::new(Vec::new());
+        let err = endpoints.connect(|url| connect(url, &attempts)).await.unwrap_err();
+        assert!(err.to_string().contains("Connection refused"));
+
+        // An unhealthy endpoint is still tried when it is the only one
+        for _ in 0..FAILURES_BEFORE_UNHEALTHY {
+            let _ = endpoints.connect(|url| connect(url, &attempts)).await;
+        }
+        assert_eq!(endpoints.preferred(), Some(PRIMARY));
+        assert_eq!(attempts.lock().unwrap().len(), 1 + FAILURES_BEFORE_UNHEALTHY as usize);
+
+        assert!(WebDriverEndpoints::new(Vec::new()).connect(|url| connect(url, &attempts)).await.is_err());
+    }
+}
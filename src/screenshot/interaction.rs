@@ -0,0 +1,137 @@
+use anyhow::{bail, Result};
+use fantoccini::{Client, Locator};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Most interactions accepted for one capture.
+pub const MAX_INTERACTIONS: usize = 20;
+/// Upper bound on each interaction, including waiting for its element.
+const INTERACTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest explicit `Wait` step.
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+/// A step performed on the page before it is captured, e.g. dismissing a
+/// cookie banner. In JSON: `{"click": "#accept"}`,
+/// `{"type": {"selector": "#q", "text": "hello"}}` or `{"wait": 500}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interaction {
+    /// Click the first element matching a CSS selector
+    Click(String),
+    /// Send keystrokes to the first element matching a CSS selector
+    Type { selector: String, text: String },
+    /// Pause for this many milliseconds
+    Wait(u64),
+}
+
+impl std::fmt::Display for Interaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Click(selector) => write!(f, "click '{}'", selector),
+            Self::Type { selector, .. } => write!(f, "type into '{}'", selector),
+            Self::Wait(ms) => write!(f, "wait {}ms", ms),
+        }
+    }
+}
+
+/// Returned when an interaction fails. The page is unlikely to behave
+/// differently on another attempt, so it is not retried.
+#[derive(Debug)]
+pub struct InteractionFailed {
+    /// Position of the failed interaction (1-based)
+    pub step: usize,
+    pub interaction: Interaction,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InteractionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interaction {} ({}) failed: {}", self.step, self.interaction, self.reason)
+    }
+}
+
+impl std::error::Error for InteractionFailed {}
+
+/// Rejects interaction lists that are too long, wait too long or have empty
+/// selectors, before anything is queued.
+pub fn validate_interactions(interactions: &[Interaction]) -> Result<()> {
+    if interactions.len() > MAX_INTERACTIONS {
+        bail!("At most {} interactions are allowed, got {}", MAX_INTERACTIONS, interactions.len());
+    }
+    for (index, interaction) in interactions.iter().enumerate() {
+        match interaction {
+            Interaction::Click(selector) | Interaction::Type { selector, .. } if selector.trim().is_empty() => {
+                bail!("Interaction {} has an empty selector", index + 1);
+            }
+            Interaction::Wait(ms) if Duration::from_millis(*ms) > MAX_WAIT => {
+                bail!("Interaction {} waits {}ms, more than the {:?} maximum", index + 1, ms, MAX_WAIT);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Performs `interactions` in order, each bounded by `INTERACTION_TIMEOUT`.
+/// Elements are waited for, so content that appears after load still works.
+pub async fn run_interactions(client: &Client, interactions: &[Interaction]) -> Result<()> {
+    for (index, interaction) in interactions.iter().enumerate() {
+        let step = async {
+            match interaction {
+                Interaction::Click(selector) => {
+                    client.wait().at_most(INTERACTION_TIMEOUT).for_element(Locator::Css(selector)).await?
+                        .click().await?;
+                }
+                Interaction::Type { selector, text } => {
+                    client.wait().at_most(INTERACTION_TIMEOUT).for_element(Locator::Css(selector)).await?
+                        .send_keys(text).await?;
+                }
+                Interaction::Wait(ms) => tokio::time::sleep(Duration::from_millis(*ms)).await,
+            }
+            Ok::<_, fantoccini::error::CmdError>(())
+        };
+        let reason = match tokio::time::timeout(INTERACTION_TIMEOUT, step).await {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {:?}", INTERACTION_TIMEOUT),
+        };
+        return Err(InteractionFailed { step: index + 1, interaction: interaction.clone(), reason }.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interaction_json() {
+        let interactions: Vec<Interaction> = serde_json::from_str(
+            r##"[{"click": "#accept"}, {"type": {"selector": "#q", "text": "hi"}}, {"wait": 250}]"##
+        ).unwrap();
+        assert_eq!(interactions, vec![
+            Interaction::Click("#accept".to_string()),
+            Interaction::Type { selector: "#q".to_string(), text: "hi".to_string() },
+            Interaction::Wait(250),
+        ]);
+        assert!(validate_interactions(&interactions).is_ok());
+    }
+
+    #[test]
+    fn test_validate_interactions() {
+        assert!(validate_interactions(&vec![Interaction::Wait(1); MAX_INTERACTIONS + 1]).is_err());
+        let err = validate_interactions(&[Interaction::Wait(1), Interaction::Click(" ".to_string())]).unwrap_err();
+        assert!(err.to_string().contains("Interaction 2"));
+        assert!(validate_interactions(&[Interaction::Wait(60_000)]).is_err());
+    }
+
+    #[test]
+    fn test_failure_names_the_step() {
+        let err = InteractionFailed {
+            step: 3,
+            interaction: Interaction::Click("button.close".to_string()),
+            reason: "no such element".to_string(),
+        };
+        assert_eq!(err.to_string(), "Interaction 3 (click 'button.close') failed: no such element");
+    }
+}
@@ -0,0 +1,98 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Minimal one-request-per-connection HTTP server for deterministic tests.
+/// The handler receives the raw request head and returns a raw response.
+pub(crate) async fn spawn_mock_server<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&str) -> String + Send + Sync + 'static,
+{
+    spawn_delayed_mock_server(Duration::ZERO, handler).await
+}
+
+/// Like `spawn_mock_server`, but waits `delay` before answering each request.
+pub(crate) async fn spawn_delayed_mock_server<F>(delay: Duration, handler: F) -> SocketAddr
+where
+    F: Fn(&str) -> String + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                tokio::time::sleep(delay).await;
+                let response = handler(&request);
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+pub(crate) fn http_response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n", status, body.len());
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    response
+}
+
+pub(crate) fn request_path(request: &str) -> &str {
+    request.split_whitespace().nth(1).unwrap_or("/")
+}
+
+thread_local! {
+    /// Messages logged on this thread, while inside `capture_logs`
+    static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Routes each record to the capture of the thread that logged it, if any.
+struct ThreadCapturingLogger;
+
+impl log::Log for ThreadCapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        CAPTURED_LOGS.with(|captured| captured.borrow().is_some())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let message = record.args().to_string();
+        CAPTURED_LOGS.with(|captured| {
+            if let Some(messages) = captured.borrow_mut().as_mut() {
+                messages.push(message);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Runs `f` and returns what it logged. The logger is installed once for the
+/// whole test binary but only records the calling thread while `f` runs, so
+/// tests running in parallel never see each other's messages.
+pub(crate) fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    static INSTALL: Once = Once::new();
+    static LOGGER: ThreadCapturingLogger = ThreadCapturingLogger;
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).expect("unit tests install no other logger");
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    CAPTURED_LOGS.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let messages = CAPTURED_LOGS.with(|captured| captured.borrow_mut().take()).unwrap_or_default();
+    (result, messages)
+}
@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use url::Url;
+
+/// Returned when a URL (or a redirect target) matches the denylist.
+#[derive(Debug)]
+pub struct DeniedUrl {
+    pub url: String,
+    pub pattern: String,
+}
+
+impl std::fmt::Display for DeniedUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "URL {} is denied by policy (matched '{}')", self.url, self.pattern)
+    }
+}
+
+impl std::error::Error for DeniedUrl {}
+
+#[derive(Debug)]
+enum Rule {
+    /// `admin.example.com`
+    Host(String),
+    /// `*.example.com`: any subdomain, not the apex itself
+    Subdomains(String),
+    /// `regex:<pattern>`, matched against the whole URL
+    Pattern(Regex),
+}
+
+/// Hosts and URL patterns that must never be crawled or screenshotted.
+#[derive(Debug, Default)]
+pub struct UrlDenylist {
+    rules: Vec<(String, Rule)>,
+}
+
+impl UrlDenylist {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut rules = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            let rule = if let Some(regex) = pattern.strip_prefix("regex:") {
+                Rule::Pattern(Regex::new(regex)
+                    .with_context(|| format!("Invalid denylist pattern: {}", pattern))?)
+            } else if let Some(domain) = pattern.strip_prefix("*.") {
+                Rule::Subdomains(format!(".{}", domain.to_ascii_lowercase()))
+            } else {
+                Rule::Host(pattern.to_ascii_lowercase())
+            };
+            rules.push((pattern.clone(), rule));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Fails with `DeniedUrl` if `url` matches any rule. Unparsable URLs are
+    /// only checked against regex rules.
+    pub fn check(&self, url: &str) -> Result<(), DeniedUrl> {
+        let host = Url::parse(url).ok()
+            .and_then(|parsed| parsed.host_str().map(str::to_ascii_lowercase));
+        let matched = self.rules.iter().find(|(_, rule)| match (rule, &host) {
+            (Rule::Host(denied), Some(host)) => host == denied,
+            (Rule::Subdomains(suffix), Some(host)) => host.ends_with(suffix.as_str()),
+            (Rule::Pattern(regex), _) => regex.is_match(url),
+            _ => false,
+        });
+        match matched {
+            Some((pattern, _)) => Err(DeniedUrl { url: url.to_string(), pattern: pattern.clone() }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denylist(patterns: &[&str]) -> UrlDenylist {
+        UrlDenylist::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_exact_host() {
+        let denylist = denylist(&["admin.internal"]);
+        assert!(denylist.check("https://ADMIN.internal/login").is_err());
+        assert!(denylist.check("https://admin.internal.example.com/").is_ok());
+        assert!(denylist.check("https://example.com/?next=admin.internal").is_ok());
+    }
+
+    #[test]
+    fn test_wildcard_and_regex() {
+        let denylist = denylist(&["*.corp.example", r"regex:^https?://[^/]+/wp-admin"]);
+        let denied = denylist.check("http://vpn.corp.example/").unwrap_err();
+        assert_eq!(denied.pattern, "*.corp.example");
+        assert!(denylist.check("http://corp.example/").is_ok());
+        assert!(denylist.check("https://blog.example.org/wp-admin/").is_err());
+        assert!(denylist.check("https://blog.example.org/posts").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(UrlDenylist::new(&["regex:(".to_string()]).is_err());
+    }
+}
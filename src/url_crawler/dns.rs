@@ -0,0 +1,100 @@
+use log::debug;
+use moka::sync::Cache;
+use std::future::Future;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Most hostnames kept in a `DnsCache`.
+const MAX_CACHED_HOSTS: u64 = 10_000;
+
+/// Shared, TTL-bounded cache of hostname lookups, so concurrent requests
+/// crawling the same hosts don't resolve them again and again. Clones share
+/// the same entries. Failed lookups are not cached.
+#[derive(Clone)]
+pub struct DnsCache {
+    cache: Cache<String, Vec<IpAddr>>,
+}
+
+impl std::fmt::Debug for DnsCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DnsCache").field("entries", &self.cache.entry_count()).finish()
+    }
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            cache: Cache::builder().max_capacity(MAX_CACHED_HOSTS).time_to_live(ttl).build(),
+        }
+    }
+
+    /// Addresses `host` resolves to, from the cache when a fresh entry exists.
+    pub async fn lookup(&self, host: &str) -> std::io::Result<Vec<IpAddr>> {
+        self.lookup_with(host, lookup_host).await
+    }
+
+    async fn lookup_with<F, Fut>(&self, host: &str, resolve: F) -> std::io::Result<Vec<IpAddr>>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = std::io::Result<Vec<IpAddr>>>,
+    {
+        let key = host.to_ascii_lowercase();
+        if let Some(ips) = self.cache.get(&key) {
+            debug!("DNS cache hit for {}", key);
+            return Ok(ips);
+        }
+        let ips = resolve(key.clone()).await?;
+        self.cache.insert(key, ips.clone());
+        Ok(ips)
+    }
+}
+
+/// Resolves `host` through the system resolver, without duplicate addresses.
+pub(crate) async fn lookup_host(host: String) -> std::io::Result<Vec<IpAddr>> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    for addr in tokio::net::lookup_host((host.as_str(), 0)).await? {
+        if !ips.contains(&addr.ip()) {
+            ips.push(addr.ip());
+        }
+    }
+    Ok(ips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_cache_hits_skip_resolution() {
+        let cache = DnsCache::new(Duration::from_secs(60));
+        let lookups = AtomicUsize::new(0);
+        let resolve = |_host: String| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            async { Ok(vec!["192.0.2.1".parse().unwrap()]) }
+        };
+
+        let first = cache.lookup_with("Example.com", resolve).await.unwrap();
+        let second = cache.clone().lookup_with("example.com", resolve).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        cache.lookup_with("other.example", resolve).await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let cache = DnsCache::new(Duration::from_millis(50));
+        let lookups = AtomicUsize::new(0);
+        let resolve = |_host: String| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            async { Ok(vec!["192.0.2.1".parse().unwrap()]) }
+        };
+
+        cache.lookup_with("example.com", resolve).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cache.lookup_with("example.com", resolve).await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+}
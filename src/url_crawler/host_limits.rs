@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Hosts whose semaphores are kept before idle ones are dropped.
+const MAX_TRACKED_HOSTS: usize = 10_000;
+
+/// Caps the requests in flight to any one host across every crawl sharing a
+/// `Crawler`, so a batch full of URLs on the same host doesn't hammer it.
+#[derive(Debug)]
+pub(crate) struct HostLimits {
+    limit: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostLimits {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self { limit: limit.max(1), hosts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Waits for a free slot on `host`; the slot is released when the permit
+    /// is dropped.
+    pub(crate) async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+            if hosts.len() >= MAX_TRACKED_HOSTS {
+                // Outstanding permits hold a reference, so busy hosts are kept
+                hosts.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            }
+            hosts.entry(host.to_ascii_lowercase())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+                .clone()
+        };
+        semaphore.acquire_owned().await.expect("host semaphores are never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_limit_is_per_host() {
+        let limits = HostLimits::new(1);
+        let held = limits.acquire("example.com").await;
+
+        // Another host is unaffected, the same host (in any case) has to wait
+        let _other = limits.acquire("other.example").await;
+        let same = tokio::time::timeout(Duration::from_millis(50), limits.acquire("EXAMPLE.com")).await;
+        assert!(same.is_err());
+
+        drop(held);
+        let same = tokio::time::timeout(Duration::from_millis(50), limits.acquire("example.com")).await;
+        assert!(same.is_ok());
+    }
+}
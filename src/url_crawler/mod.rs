@@ -1,23 +1,113 @@
+mod denylist;
+mod dns;
+mod host_limits;
+
+pub use denylist::{DeniedUrl, UrlDenylist};
+pub use dns::DnsCache;
+
+use host_limits::HostLimits;
+
 use anyhow::{Result, Context, bail};
 use log::{info, warn, debug, error};
-use reqwest::{Client, header::{HeaderMap, HeaderValue, USER_AGENT}};
-use std::collections::HashSet;
-use std::time::Duration;
-use url::Url;
+use moka::sync::Cache;
+use reqwest::{Client, Method, StatusCode, header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, HOST, USER_AGENT}};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{field, info_span, Instrument};
+use rand::Rng;
+use std::borrow::Cow;
+use url::{Host, Url};
+
+use crate::utils::anonymizer::loggable_url;
 
 const MAX_HOPS: usize = 10;
 const MAX_URL_LENGTH: usize = 2048;
 const REQUEST_TIMEOUT: u64 = 30; // seconds
 const RATE_LIMIT_DELAY: u64 = 1; // seconds
+/// Default for `CrawlerConfig::max_response_bytes`.
+const MAX_RESPONSE_BYTES: u64 = 5 * 1024 * 1024;
+/// Default for `CrawlerConfig::max_captured_body_bytes`.
+const MAX_CAPTURED_BODY_BYTES: u64 = 20 * 1024 * 1024;
+/// The standard redirect statuses: 301, 302, 303, 307 and 308.
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+/// Most clients kept by a `Crawler` for hops pinned by `block_private_ips`.
+const MAX_PINNED_CLIENTS: u64 = 1_000;
+/// How long an unused pinned client is kept, like idle pooled connections.
+const PINNED_CLIENT_IDLE: Duration = Duration::from_secs(90);
 
+#[derive(Debug, Clone)]
 pub struct CrawlerConfig {
     pub max_hops: usize,
     pub max_url_length: usize,
     pub request_timeout: Duration,
     pub rate_limit_delay: Duration,
+    /// Randomizes each hop's delay to `rate_limit_delay ± jitter` (never
+    /// below zero) so batch crawls look less robotic
+    pub jitter: Option<Duration>,
     pub allowed_schemes: Vec<String>,
     pub allowed_domains: Option<Vec<String>>,
     pub user_agent: String,
+    /// Pins hostnames to fixed addresses, bypassing DNS while keeping the
+    /// original Host header and SNI. The port of the URL is still used.
+    pub host_overrides: Vec<(String, SocketAddr)>,
+    /// Wall-clock budget for the whole chain, complementing `max_hops`
+    pub max_total_duration: Option<Duration>,
+    /// Enumerate the chain with `HEAD` requests instead of `GET`.
+    ///
+    /// This is a cheap triage mode: no response bodies are downloaded, so the
+    /// chain is found much faster. The tradeoff is reduced fidelity, since some
+    /// servers answer `HEAD` differently from `GET` (or not at all). Hops that
+    /// reject `HEAD` with 405/501 are retried with `GET`.
+    pub use_head_requests: bool,
+    /// Resolve each hop's hostname and record its IP addresses. Off by default
+    /// because it adds a DNS lookup per hop.
+    pub resolve_hop_ips: bool,
+    /// Hosts (`admin.example.com`), subdomain wildcards (`*.corp.example`) or
+    /// `regex:` URL patterns that are never requested; see `UrlDenylist`
+    pub denylist: Vec<String>,
+    /// Statuses whose `Location` is followed; a response with any other
+    /// status ends the chain, even if it carries a `Location` header
+    pub followed_statuses: HashSet<u16>,
+    /// Refuse (with `DeniedUrl`) any hop whose host resolves to a private,
+    /// loopback or link-local address, so neither the crawler nor the
+    /// browser can be pointed at internal services
+    pub block_private_ips: bool,
+    /// Sends the first request as a `POST` with this body (e.g. a login form).
+    /// Redirects follow the usual method rules: 307/308 repeat the `POST`,
+    /// 301/302/303 continue with `GET`. Resumed crawls start with `GET`.
+    pub body: Option<Vec<u8>>,
+    /// `Content-Type` sent with `body`
+    pub body_content_type: String,
+    /// Cache for the lookups made by `resolve_hop_ips` and `block_private_ips`.
+    /// Every clone of this config shares it, so one cache set on the server's
+    /// config serves all requests.
+    pub dns_cache: Option<DnsCache>,
+    /// Stop reading (and counting) a response body after this many bytes
+    pub max_response_bytes: u64,
+    /// Keep each hop's response body (up to `max_response_bytes`) in
+    /// `RedirectHop::body`, so the final page doesn't have to be fetched
+    /// again. Off by default to keep results small.
+    pub capture_bodies: bool,
+    /// Most body bytes kept across all hops of one crawl with
+    /// `capture_bodies`; later bodies are cut short or left out, and
+    /// `RedirectResult::bodies_truncated` is set
+    pub max_captured_body_bytes: u64,
+    /// Most requests in flight to one host at a time, across every crawl
+    /// sharing a `Crawler`; unlimited when unset
+    pub max_connections_per_host: Option<usize>,
+    /// Follow `Location` headers; when off only the first URL is requested
+    /// and a redirect ends the chain with `RedirectsDisabled`
+    pub follow_redirects: bool,
+    /// Mask credentials and query values of the URLs in log messages (see
+    /// `redact_url`); requests always use the full URL
+    pub redact_logs: bool,
+    /// `Host` header sent instead of the URL's host, e.g. to crawl
+    /// `http://192.0.2.10/` as `example.com`. Only hops on the start URL's
+    /// origin carry it, so a redirect elsewhere never receives it.
+    pub host_header: Option<String>,
 }
 
 impl Default for CrawlerConfig {
@@ -27,139 +117,719 @@ impl Default for CrawlerConfig {
             max_url_length: MAX_URL_LENGTH,
             request_timeout: Duration::from_secs(REQUEST_TIMEOUT),
             rate_limit_delay: Duration::from_secs(RATE_LIMIT_DELAY),
+            jitter: None,
             allowed_schemes: vec!["http".to_string(), "https".to_string()],
             allowed_domains: None,
             user_agent: "ScreenshotAPI/1.0".to_string(),
+            host_overrides: Vec::new(),
+            max_total_duration: None,
+            use_head_requests: false,
+            resolve_hop_ips: false,
+            denylist: Vec::new(),
+            followed_statuses: REDIRECT_STATUSES.into_iter().collect(),
+            block_private_ips: false,
+            body: None,
+            body_content_type: "application/x-www-form-urlencoded".to_string(),
+            dns_cache: None,
+            max_response_bytes: MAX_RESPONSE_BYTES,
+            capture_bodies: false,
+            max_captured_body_bytes: MAX_CAPTURED_BODY_BYTES,
+            max_connections_per_host: None,
+            follow_redirects: true,
+            redact_logs: true,
+            host_header: None,
         }
     }
 }
 
-pub async fn crawl_redirect_chain(start_url: &str) -> Result<Vec<String>> {
-    crawl_redirect_chain_with_config(start_url, &CrawlerConfig::default()).await
+/// Why the crawler stopped following the redirect chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerminationReason {
+    /// The last URL in the chain did not redirect any further
+    Completed,
+    /// A URL in the chain was visited twice
+    RedirectLoop,
+    /// `max_hops` redirects were followed
+    MaxHops,
+    /// A redirect pointed at a scheme outside `allowed_schemes`
+    DisallowedScheme,
+    /// `max_total_duration` elapsed before the chain finished
+    TimeBudgetExceeded,
+    /// The first URL redirected, but `follow_redirects` is off
+    RedirectsDisabled,
 }
 
-pub async fn crawl_redirect_chain_with_config(start_url: &str, config: &CrawlerConfig) -> Result<Vec<String>> {
-    debug!("Starting URL crawl with config: max_hops={}, max_url_length={}, timeout={:?}, rate_limit={:?}",
-        config.max_hops, config.max_url_length, config.request_timeout, config.rate_limit_delay);
+/// A single URL visited while following the chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct RedirectHop {
+    pub url: String,
+    /// HTTP status, or `None` if the crawl stopped before requesting this URL
+    pub status: Option<u16>,
+    /// Addresses the hostname resolved to (only with `resolve_hop_ips`)
+    pub resolved_ips: Vec<IpAddr>,
+    /// Response body, lossily decoded as UTF-8 (only with `capture_bodies`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
 
-    // Validate input URL
-    if start_url.is_empty() {
-        error!("Empty URL provided");
-        bail!("URL cannot be empty");
-    }
-    if start_url.len() > config.max_url_length {
-        error!("URL length {} exceeds maximum allowed length of {}", start_url.len(), config.max_url_length);
-        bail!("URL exceeds maximum length of {} characters", config.max_url_length);
+#[derive(Debug, Clone)]
+pub struct RedirectResult {
+    pub chain: Vec<String>,
+    pub hops: Vec<RedirectHop>,
+    pub termination: TerminationReason,
+    /// Media type (without parameters) of the last response, e.g. `text/html`
+    pub final_content_type: Option<String>,
+    /// Response body bytes downloaded across all hops
+    pub bytes_transferred: u64,
+    /// Some hop's body was cut short or left out because the crawl's bodies
+    /// reached `CrawlerConfig::max_captured_body_bytes`
+    pub bodies_truncated: bool,
+    /// Every URL visited so far, including those of crawls this one resumed
+    pub visited_urls: Vec<String>,
+    /// Redirect target that was not followed because `max_hops` was reached;
+    /// pass the result to `Crawler::resume` to continue from it
+    pub next_url: Option<String>,
+}
+
+impl RedirectResult {
+    pub fn final_url(&self) -> Option<&str> {
+        self.chain.last().map(String::as_str)
     }
+}
 
-    let parsed_url = Url::parse(start_url)
-        .context("Failed to parse URL")?;
+/// Redirect crawler that owns a configured HTTP client.
+///
+/// Build it once and reuse it across crawls so connections are pooled instead
+/// of creating a new client for every request.
+#[derive(Clone)]
+pub struct Crawler {
+    client: Client,
+    config: CrawlerConfig,
+    denylist: Arc<UrlDenylist>,
+    host_limits: Option<Arc<HostLimits>>,
+    /// Clients pinned to a host's checked addresses, keyed by host and
+    /// sorted addresses, so repeat hops reuse their connections
+    pinned_clients: Cache<(String, Vec<SocketAddr>), Client>,
+}
 
-    // Validate URL scheme
-    if !config.allowed_schemes.contains(&parsed_url.scheme().to_string()) {
-        error!("Disallowed URL scheme: {}", parsed_url.scheme());
-        bail!("URL scheme '{}' is not allowed", parsed_url.scheme());
+impl Crawler {
+    pub fn new(config: CrawlerConfig) -> Result<Self> {
+        let client = build_http_client(&config)?;
+        let denylist = Arc::new(UrlDenylist::new(&config.denylist)?);
+        let host_limits = config.max_connections_per_host.map(|limit| Arc::new(HostLimits::new(limit)));
+        let pinned_clients = Cache::builder()
+            .max_capacity(MAX_PINNED_CLIENTS)
+            .time_to_idle(PINNED_CLIENT_IDLE)
+            .build();
+        Ok(Self { client, config, denylist, host_limits, pinned_clients })
     }
 
-    debug!("Initializing HTTP client with user agent: {}", config.user_agent);
-    // Configure client with custom settings
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
+    /// Whether hops resolving to private addresses are refused; see
+    /// `CrawlerConfig::block_private_ips`.
+    pub fn blocks_private_ips(&self) -> bool {
+        self.config.block_private_ips
+    }
 
-    let client = Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .timeout(config.request_timeout)
-        .default_headers(headers)
-        .pool_idle_timeout(Duration::from_secs(90))
-        .build()?;
+    /// A crawler with a different hop limit that shares this one's HTTP
+    /// client, and so its connection pool and per-host limits.
+    pub fn with_max_hops(&self, max_hops: usize) -> Self {
+        let mut crawler = self.clone();
+        crawler.config.max_hops = max_hops;
+        crawler
+    }
+
+    /// A crawler that only requests the URL it is given, sharing this one's
+    /// HTTP client; see `CrawlerConfig::follow_redirects`.
+    pub fn without_redirects(&self) -> Self {
+        let mut crawler = self.clone();
+        crawler.config.follow_redirects = false;
+        crawler
+    }
 
-    let mut chain = Vec::new();
-    let mut visited_urls = HashSet::new();
-    let mut current_url = start_url.to_string();
-    let mut hops = 0;
+    /// A crawler sending `host` as the `Host` header of hops on the start
+    /// URL's origin, sharing this one's HTTP client; see
+    /// `CrawlerConfig::host_header`.
+    pub fn with_host_header(&self, host: &str) -> Self {
+        let mut crawler = self.clone();
+        crawler.config.host_header = Some(host.to_string());
+        crawler
+    }
 
-    loop {
-        // Check for redirect loops
-        if !visited_urls.insert(current_url.clone()) {
-            error!("Redirect loop detected at {}", current_url);
-            break;
+    /// A client that connects to `url`'s host only at `ips`, for hops whose
+    /// addresses were checked by `block_private_ips`. `None` when there is
+    /// nothing to pin: IP literals and `host_overrides` are never re-resolved.
+    /// Clients are cached per host and addresses, and shared by clones.
+    fn pinned_client(&self, url: &str, ips: &[IpAddr]) -> Result<Option<Client>> {
+        let parsed = Url::parse(url)?;
+        let Some(Host::Domain(domain)) = parsed.host() else {
+            return Ok(None);
+        };
+        if self.config.host_overrides.iter().any(|(host, _)| host == domain) {
+            return Ok(None);
         }
+        let port = parsed.port_or_known_default().unwrap_or(0);
+        let mut addrs: Vec<SocketAddr> = ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect();
+        addrs.sort();
+        let key = (domain.to_ascii_lowercase(), addrs);
+        if let Some(client) = self.pinned_clients.get(&key) {
+            return Ok(Some(client));
+        }
+        debug!("Pinning {} to checked addresses {:?}", domain, key.1);
+        let client = http_client_builder(&self.config)?.resolve_to_addrs(domain, &key.1).build()?;
+        self.pinned_clients.insert(key, client.clone());
+        Ok(Some(client))
+    }
 
-        info!("Crawling URL: {} (hop {}/{})", current_url, hops + 1, config.max_hops);
-        chain.push(current_url.clone());
+    /// `url` as it may appear in logs; see `CrawlerConfig::redact_logs`.
+    fn loggable<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        loggable_url(url, self.config.redact_logs)
+    }
+
+    pub async fn crawl(&self, start_url: &str) -> Result<RedirectResult> {
+        self.crawl_with_visited(start_url, &[], self.config.body.clone()).await
+    }
+
+    /// Continues a chain that stopped at `max_hops`, with a fresh hop budget.
+    /// URLs visited by `previous` still count for loop detection.
+    pub async fn resume(&self, previous: &RedirectResult) -> Result<RedirectResult> {
+        let Some(next_url) = &previous.next_url else {
+            bail!("The previous crawl did not stop at a pending redirect");
+        };
+        self.crawl_with_visited(next_url, &previous.visited_urls, None).await
+    }
 
-        // Rate limiting
-        if hops > 0 {
-            debug!("Rate limiting: waiting for {:?}", config.rate_limit_delay);
-            tokio::time::sleep(config.rate_limit_delay).await;
+    async fn crawl_with_visited(
+        &self,
+        start_url: &str,
+        previously_visited: &[String],
+        mut request_body: Option<Vec<u8>>,
+    ) -> Result<RedirectResult> {
+        let config = &self.config;
+        debug!("Starting URL crawl with config: max_hops={}, max_url_length={}, timeout={:?}, rate_limit={:?}",
+            config.max_hops, config.max_url_length, config.request_timeout, config.rate_limit_delay);
+
+        // Validate input URL
+        if start_url.is_empty() {
+            error!("Empty URL provided");
+            bail!("URL cannot be empty");
+        }
+        if start_url.len() > config.max_url_length {
+            error!("URL length {} exceeds maximum allowed length of {}", start_url.len(), config.max_url_length);
+            bail!("URL exceeds maximum length of {} characters", config.max_url_length);
         }
 
-        debug!("Sending request to {}", current_url);
-        let resp = client.get(&current_url)
-            .send()
-            .await
-            .context("Failed to send request")?;
+        let parsed_url = Url::parse(start_url)
+            .context("Failed to parse URL")?;
+
+        // Validate URL scheme
+        if !config.allowed_schemes.contains(&parsed_url.scheme().to_string()) {
+            error!("Disallowed URL scheme: {}", parsed_url.scheme());
+            bail!("URL scheme '{}' is not allowed", parsed_url.scheme());
+        }
+        self.denylist.check(start_url)?;
 
-        debug!("Response status: {}", resp.status());
+        let mut chain = Vec::new();
+        let mut redirect_hops: Vec<RedirectHop> = Vec::new();
+        let mut visited_urls = HashSet::new();
+        let mut visited_keys: HashMap<String, usize> = HashMap::new();
+        for url in previously_visited {
+            visited_urls.insert(url.clone());
+            *visited_keys.entry(loop_key(url)).or_insert(0) += 1;
+        }
+        let mut next_url_pending = None;
+        let mut current_url = start_url.to_string();
+        let mut hops = 0;
+        let mut termination = TerminationReason::Completed;
+        let mut final_content_type = None;
+        let mut bytes_transferred = 0;
+        let mut captured_body_bytes = 0;
+        let mut bodies_truncated = false;
+        let start_origin = Url::parse(start_url).ok().map(|url| url.origin());
+        let started = Instant::now();
 
-        if let Some(location) = resp.headers().get(reqwest::header::LOCATION) {
-            if hops >= config.max_hops {
-                warn!("Max redirect hops ({}) reached at {}", config.max_hops, current_url);
+        loop {
+            // Check for redirect loops. One respelling of a URL is a normal
+            // canonicalization (`/docs` -> `/docs/`); a third spelling of the
+            // same normalized URL means an oscillation like `/a` <-> `/a/`.
+            let spellings = visited_keys.entry(loop_key(&current_url)).or_insert(0);
+            *spellings += 1;
+            if !visited_urls.insert(current_url.clone()) || *spellings > 2 {
+                error!("Redirect loop detected at {}", self.loggable(&current_url));
+                termination = TerminationReason::RedirectLoop;
                 break;
             }
 
-            let location_str = location.to_str()?;
-            debug!("Found redirect location: {}", location_str);
-            let next_url = if location_str.starts_with("http") {
-                location_str.to_string()
+            info!("Crawling URL: {} (hop {}/{})", self.loggable(&current_url), hops + 1, config.max_hops);
+            let resolved_ips = if config.resolve_hop_ips || config.block_private_ips {
+                resolve_hop_ips(&current_url, &config.host_overrides, config.dns_cache.as_ref()).await
             } else {
-                // Handle relative redirects
-                let base = Url::parse(&current_url)?;
-                base.join(location_str)?.to_string()
+                Vec::new()
+            };
+            // Checked for every hop, so redirects onto internal hosts are caught too
+            if config.block_private_ips {
+                // Fail closed: a host that can't be checked is not requested
+                if resolved_ips.is_empty() {
+                    error!("Refusing {}: its host did not resolve", self.loggable(&current_url));
+                    return Err(DeniedUrl { url: current_url, pattern: "unresolvable host".to_string() }.into());
+                }
+                if let Some(ip) = resolved_ips.iter().find(|ip| is_private_ip(**ip)) {
+                    error!("Refusing {}: it resolves to private address {}", self.loggable(&current_url), ip);
+                    return Err(DeniedUrl { url: current_url, pattern: format!("private address {}", ip) }.into());
+                }
+            }
+            // reqwest would resolve the host again on its own, so a rebinding
+            // host could pass the check and then be fetched at a private
+            // address; pin the request to the addresses just checked instead
+            let pinned_client = match config.block_private_ips {
+                true => self.pinned_client(&current_url, &resolved_ips)?,
+                false => None,
             };
+            let client = pinned_client.as_ref().unwrap_or(&self.client);
+            chain.push(current_url.clone());
+            redirect_hops.push(RedirectHop {
+                url: current_url.clone(),
+                status: None,
+                resolved_ips: if config.resolve_hop_ips { resolved_ips } else { Vec::new() },
+                body: None,
+            });
 
-            // Validate redirect URL
-            let next_parsed = Url::parse(&next_url)
-                .context("Failed to parse redirect URL")?;
+            // Rate limiting
+            if hops > 0 {
+                let delay = hop_delay(config.rate_limit_delay, config.jitter);
+                debug!("Rate limiting: waiting for {:?}", delay);
+                tokio::time::sleep(delay).await;
+            }
 
-            // Check scheme
-            if !config.allowed_schemes.contains(&next_parsed.scheme().to_string()) {
-                warn!("Redirect to disallowed scheme: {} (from {})", next_parsed.scheme(), current_url);
-                break;
+            // Enforce the overall time budget, bounding the next request by what is left of it
+            let mut request_timeout = config.request_timeout;
+            if let Some(budget) = config.max_total_duration {
+                let remaining = budget.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    warn!("Crawl time budget ({:?}) exceeded at {}", budget, self.loggable(&current_url));
+                    termination = TerminationReason::TimeBudgetExceeded;
+                    break;
+                }
+                request_timeout = remaining.min(config.request_timeout);
+            }
+
+            // One span per hop so tracing exporters can attribute crawl latency
+            let hop_span = info_span!(
+                "redirect_hop",
+                url = %self.loggable(&current_url),
+                hop_index = hops,
+                status = field::Empty,
+            );
+
+            // Held until the body is read; waiting for it counts against the time budget
+            let _host_slot = match &self.host_limits {
+                Some(limits) => {
+                    let host = Url::parse(&current_url).ok()
+                        .and_then(|url| url.host_str().map(str::to_string))
+                        .unwrap_or_default();
+                    Some(limits.acquire(&host).await)
+                }
+                None => None,
+            };
+
+            let method = match &request_body {
+                Some(_) => Method::POST,
+                None if config.use_head_requests => Method::HEAD,
+                None => Method::GET,
+            };
+            debug!("Sending {} request to {}", method, self.loggable(&current_url));
+            let host_header = config.host_header.as_deref()
+                .filter(|_| Url::parse(&current_url).ok().map(|url| url.origin()) == start_origin);
+            let mut request = client.request(method.clone(), &current_url).timeout(request_timeout);
+            if let Some(host) = host_header {
+                request = request.header(HOST, host);
+            }
+            if let Some(body) = &request_body {
+                request = request.header(CONTENT_TYPE, config.body_content_type.as_str()).body(body.clone());
+            }
+            let mut result = request
+                .send()
+                .instrument(hop_span.clone())
+                .await;
+            if method == Method::HEAD {
+                if let Ok(resp) = &result {
+                    if matches!(resp.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED) {
+                        debug!("HEAD rejected with {} at {}, retrying with GET", resp.status(), self.loggable(&current_url));
+                        let mut retry = client.get(&current_url).timeout(request_timeout);
+                        if let Some(host) = host_header {
+                            retry = retry.header(HOST, host);
+                        }
+                        result = retry
+                            .send()
+                            .instrument(hop_span.clone())
+                            .await;
+                    }
+                }
+            }
+
+            let resp = match result {
+                Ok(resp) => resp,
+                Err(e) if e.is_timeout() && config.max_total_duration.is_some_and(|budget| started.elapsed() >= budget) => {
+                    warn!("Crawl time budget exceeded while requesting {}", self.loggable(&current_url));
+                    termination = TerminationReason::TimeBudgetExceeded;
+                    break;
+                }
+                Err(e) => return Err(e).context("Failed to send request"),
+            };
+
+            let status = resp.status().as_u16();
+            hop_span.record("status", status);
+            if let Some(hop) = redirect_hops.last_mut() {
+                hop.status = Some(status);
+            }
+            debug!("Response status: {}", resp.status());
+            final_content_type = media_type(resp.headers());
+            if let Some(encoding) = unexpected_content_encoding(resp.headers()) {
+                warn!("{} sent Content-Encoding '{}' although only identity was accepted; its body cannot be decoded",
+                    self.loggable(&current_url), encoding);
+            }
+
+            let location = resp.headers().get(reqwest::header::LOCATION).cloned();
+            // Bodies share one budget per crawl, so a long chain of large
+            // pages can't fill memory with `max_response_bytes` per hop
+            let remaining_budget = config.max_captured_body_bytes.saturating_sub(captured_body_bytes);
+            let capture_limit = config.capture_bodies.then(|| config.max_response_bytes.min(remaining_budget));
+            let (downloaded, body) = drain_body(resp, &self.loggable(&current_url), config.max_response_bytes, capture_limit).await;
+            bytes_transferred += downloaded;
+            let body = body.filter(|_| remaining_budget > 0);
+            if config.capture_bodies && remaining_budget < config.max_response_bytes
+                && downloaded > body.as_ref().map_or(0, |body| body.len() as u64)
+            {
+                warn!("Captured bodies reached {} bytes, cutting short the body of {}",
+                    config.max_captured_body_bytes, self.loggable(&current_url));
+                bodies_truncated = true;
+            }
+            captured_body_bytes += body.as_ref().map_or(0, |body| body.len() as u64);
+            if let Some(hop) = redirect_hops.last_mut() {
+                hop.body = body.map(|body| String::from_utf8_lossy(&body).into_owned());
             }
+            let location = match location {
+                Some(_) if !config.followed_statuses.contains(&status) => {
+                    debug!("Not following Location of {} response at {}", status, self.loggable(&current_url));
+                    None
+                }
+                location => location,
+            };
+
+            if let Some(location) = location {
+                if !config.follow_redirects {
+                    info!("Not following redirect from {}: following is disabled", self.loggable(&current_url));
+                    termination = TerminationReason::RedirectsDisabled;
+                    next_url_pending = location.to_str().ok()
+                        .and_then(|location_str| resolve_location(&current_url, location_str).ok());
+                    break;
+                }
+                if hops >= config.max_hops {
+                    warn!("Max redirect hops ({}) reached at {}", config.max_hops, self.loggable(&current_url));
+                    termination = TerminationReason::MaxHops;
+                    next_url_pending = location.to_str().ok()
+                        .and_then(|location_str| resolve_location(&current_url, location_str).ok());
+                    break;
+                }
+
+                let location_str = location.to_str()?;
+                debug!("Found redirect location: {}", self.loggable(location_str));
+                let next_url = resolve_location(&current_url, location_str)?;
+
+                // Validate redirect URL
+                let next_parsed = Url::parse(&next_url)
+                    .context("Failed to parse redirect URL")?;
+
+                // Check scheme
+                if !config.allowed_schemes.contains(&next_parsed.scheme().to_string()) {
+                    warn!("Redirect to disallowed scheme: {} (from {})", next_parsed.scheme(), self.loggable(&current_url));
+                    termination = TerminationReason::DisallowedScheme;
+                    break;
+                }
 
-            // Check domain if whitelist is configured
-            if let Some(allowed_domains) = &config.allowed_domains {
-                if let Some(host) = next_parsed.host_str() {
-                    if !allowed_domains.iter().any(|d| host.ends_with(d)) {
-                        error!("Redirect to disallowed domain: {} (from {})", host, current_url);
-                        bail!("Redirect to disallowed domain: {}", host);
+                // Check domain if whitelist is configured
+                if let Some(allowed_domains) = &config.allowed_domains {
+                    if let Some(host) = next_parsed.host_str() {
+                        if !allowed_domains.iter().any(|d| host.ends_with(d)) {
+                            error!("Redirect to disallowed domain: {} (from {})", host, self.loggable(&current_url));
+                            bail!("Redirect to disallowed domain: {}", host);
+                        }
                     }
                 }
+
+                if let Err(denied) = self.denylist.check(&next_url) {
+                    error!("Redirect from {} to {} denied by policy (matched '{}')",
+                        self.loggable(&current_url), self.loggable(&next_url), denied.pattern);
+                    return Err(denied.into());
+                }
+
+                // Only 307/308 repeat the request as is; the others (303 by
+                // definition, 301/302 by universal practice) switch to GET
+                if !matches!(status, 307 | 308) {
+                    request_body = None;
+                }
+
+                info!("Redirected to: {} (hop {}/{})", self.loggable(&next_url), hops + 1, config.max_hops);
+                current_url = next_url;
+                hops += 1;
+            } else {
+                debug!("No more redirects found, ending crawl");
+                break;
+            }
+        }
+
+        info!("Completed URL crawl: found {} URLs in chain ({:?})", chain.len(), termination);
+        let mut visited = previously_visited.to_vec();
+        visited.extend(chain.iter().cloned());
+        Ok(RedirectResult {
+            chain,
+            hops: redirect_hops,
+            termination,
+            final_content_type,
+            bytes_transferred,
+            bodies_truncated,
+            visited_urls: visited,
+            next_url: next_url_pending,
+        })
+    }
+}
+
+/// Resolves a `Location` header against the URL that sent it. `Url::join`
+/// handles absolute, scheme-relative (`//host/path`), path-relative and
+/// fragment-bearing values alike, so no prefix sniffing is needed.
+fn resolve_location(current_url: &str, location: &str) -> Result<String> {
+    let base = Url::parse(current_url)?;
+    Ok(base.join(location.trim())?.to_string())
+}
+
+async fn resolve_hop_ips(url: &str, host_overrides: &[(String, SocketAddr)], dns_cache: Option<&DnsCache>) -> Vec<IpAddr> {
+    let Ok(parsed) = Url::parse(url) else {
+        return Vec::new();
+    };
+    let domain = match parsed.host() {
+        Some(Host::Ipv4(ip)) => return vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => return vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => domain.to_string(),
+        None => return Vec::new(),
+    };
+    if let Some((_, addr)) = host_overrides.iter().find(|(host, _)| *host == domain) {
+        return vec![addr.ip()];
+    }
+
+    let lookup = match dns_cache {
+        Some(cache) => cache.lookup(&domain).await,
+        None => dns::lookup_host(domain.clone()).await,
+    };
+    match lookup {
+        Ok(ips) => {
+            debug!("Resolved {} to {:?}", domain, ips);
+            ips
+        }
+        Err(e) => {
+            warn!("Failed to resolve {}: {}", domain, e);
+            Vec::new()
+        }
+    }
+}
+
+/// `base` shifted by a uniformly random offset in `[-jitter, +jitter]`,
+/// clamped at zero.
+fn hop_delay(base: Duration, jitter: Option<Duration>) -> Duration {
+    match jitter {
+        Some(jitter) if !jitter.is_zero() => {
+            let offset = rand::thread_rng().gen_range(-jitter.as_secs_f64()..=jitter.as_secs_f64());
+            Duration::from_secs_f64((base.as_secs_f64() + offset).max(0.0))
+        }
+        _ => base,
+    }
+}
+
+/// Addresses that must not be reachable through the service: RFC 1918,
+/// loopback, link-local (including cloud metadata at 169.254.169.254),
+/// carrier-grade NAT, `0.0.0.0/8`, multicast, broadcast and IPv6 unique-local
+/// ranges. IPv6 addresses embedding an IPv4 one (mapped, IPv4-compatible,
+/// NAT64 `64:ff9b::/96` and 6to4 `2002::/16`) are judged by that address.
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_broadcast() || v4.is_multicast()
+                || a == 0 || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            let embedded_v4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+            let embedded = match segments {
+                // ::a.b.c.d and ::ffff:a.b.c.d; this covers :: and ::1 too
+                [0, 0, 0, 0, 0, 0 | 0xffff, high, low] => Some(embedded_v4(high, low)),
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(embedded_v4(high, low)),
+                [0x2002, high, low, ..] => Some(embedded_v4(high, low)),
+                _ => None,
+            };
+            if let Some(v4) = embedded {
+                return is_private_ip(IpAddr::V4(v4));
             }
+            let first = segments[0];
+            v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // fc00::/7 unique local
+                || (first & 0xffc0) == 0xfe80 // fe80::/10 link-local
+        }
+    }
+}
 
-            info!("Redirected to: {} (hop {}/{})", next_url, hops + 1, config.max_hops);
-            current_url = next_url;
-            hops += 1;
-        } else {
-            debug!("No more redirects found, ending crawl");
-            break;
+/// A private address (see `is_private_ip`) that `url`'s host is or resolves
+/// to through the system resolver, for checking what a browser visited.
+/// Hosts that don't resolve have none.
+pub(crate) async fn private_address(url: &str) -> Option<IpAddr> {
+    resolve_hop_ips(url, &[], None).await.into_iter().find(|ip| is_private_ip(*ip))
+}
+
+/// Reads the response body, returning how many bytes were downloaded and,
+/// with a `capture_limit`, up to that many bytes of the body itself. Reading
+/// stops at `max_bytes` so a huge final page can't stall the crawl or fill memory.
+async fn drain_body(mut resp: reqwest::Response, url: &str, max_bytes: u64, capture_limit: Option<u64>) -> (u64, Option<Vec<u8>>) {
+    let mut total = 0;
+    let mut body = capture_limit.map(|_| Vec::new());
+    while total < max_bytes {
+        match resp.chunk().await {
+            Ok(Some(chunk)) => {
+                total += chunk.len() as u64;
+                if let Some(body) = &mut body {
+                    let room = (capture_limit.unwrap_or(0) as usize).saturating_sub(body.len());
+                    body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Stopped reading body of {} after {} bytes: {}", url, total, e);
+                break;
+            }
         }
     }
+    (total, body)
+}
+
+fn media_type(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let media_type = value.split(';').next()?.trim().to_ascii_lowercase();
+    (!media_type.is_empty()).then_some(media_type)
+}
+
+/// The client is built without decompression support, so anything other than
+/// an identity encoding means the body would be unreadable.
+fn unexpected_content_encoding(headers: &HeaderMap) -> Option<String> {
+    let encoding = headers.get(CONTENT_ENCODING)?.to_str().unwrap_or("<invalid>").trim();
+    (!encoding.is_empty() && !encoding.eq_ignore_ascii_case("identity")).then(|| encoding.to_string())
+}
+
+/// Key used for redirect loop detection: scheme, host and default port are
+/// normalized by `Url`, the fragment is dropped and a trailing slash on the
+/// path is ignored. Unparsable URLs fall back to the raw string.
+fn loop_key(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    parsed.set_fragment(None);
+    let path = parsed.path().trim_end_matches('/').to_string();
+    parsed.set_path(&path);
+    parsed.to_string()
+}
+
+fn build_http_client(config: &CrawlerConfig) -> Result<Client> {
+    debug!("Initializing HTTP client with user agent: {}", config.user_agent);
+    Ok(http_client_builder(config)?.build()?)
+}
+
+fn http_client_builder(config: &CrawlerConfig) -> Result<reqwest::ClientBuilder> {
+    // Configure client with custom settings
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
+    // Bodies are read undecoded, so ask servers not to compress them
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+
+    let mut builder = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(config.request_timeout)
+        .default_headers(headers)
+        .pool_idle_timeout(Duration::from_secs(90));
+
+    for (host, addr) in &config.host_overrides {
+        debug!("Overriding DNS resolution for {} -> {}", host, addr);
+        builder = builder.resolve(host, *addr);
+    }
 
-    info!("Completed URL crawl: found {} URLs in chain", chain.len());
-    Ok(chain)
+    Ok(builder)
+}
+
+#[allow(dead_code)]
+pub async fn crawl_redirect_chain(start_url: &str) -> Result<RedirectResult> {
+    crawl_redirect_chain_with_config(start_url, &CrawlerConfig::default()).await
+}
+
+#[allow(dead_code)]
+pub async fn crawl_redirect_chain_with_config(start_url: &str, config: &CrawlerConfig) -> Result<RedirectResult> {
+    Crawler::new(config.clone())?.crawl(start_url).await
+}
+
+/// Crawls every URL with one shared config, at most `max_concurrent` at a
+/// time. Each result is paired with the URL it belongs to, in input order,
+/// so one failing URL doesn't hide the others.
+pub async fn crawl_multiple_urls(urls: &[String], config: &CrawlerConfig, max_concurrent: usize) -> Vec<(String, Result<RedirectResult>)> {
+    match Crawler::new(config.clone()) {
+        Ok(crawler) => crawl_multiple_with_crawler(urls, &crawler, max_concurrent).await,
+        Err(e) => urls.iter().map(|url| (url.clone(), Err(anyhow::anyhow!("{:#}", e)))).collect(),
+    }
+}
+
+/// Like `crawl_multiple_urls`, but through an existing `crawler`, so the
+/// batch shares its connection pool and per-host limits with everything
+/// else using it.
+pub async fn crawl_multiple_with_crawler(urls: &[String], crawler: &Crawler, max_concurrent: usize) -> Vec<(String, Result<RedirectResult>)> {
+    let jobs = urls.iter().map(|url| (url.clone(), Ok(crawler.clone()))).collect();
+    crawl_concurrently(jobs, max_concurrent).await
+}
+
+/// Like `crawl_multiple_urls`, but each URL brings its own config (e.g. a
+/// different `max_hops` per target). Results are paired with their URL, in
+/// input order.
+pub async fn crawl_multiple_with_configs(items: &[(String, CrawlerConfig)], max_concurrent: usize) -> Vec<(String, Result<RedirectResult>)> {
+    let jobs = items.iter()
+        .map(|(url, config)| (url.clone(), Crawler::new(config.clone())))
+        .collect();
+    crawl_concurrently(jobs, max_concurrent).await
+}
+
+async fn crawl_concurrently(jobs: Vec<(String, Result<Crawler>)>, max_concurrent: usize) -> Vec<(String, Result<RedirectResult>)> {
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent.max(1)));
+    let handles: Vec<_> = jobs.into_iter()
+        .map(|(url, crawler)| {
+            let semaphore = semaphore.clone();
+            let task_url = url.clone();
+            let handle = tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                crawler?.crawl(&task_url).await
+            });
+            (url, handle)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for (url, handle) in handles {
+        let result = handle.await.unwrap_or_else(|e| Err(anyhow::anyhow!("Crawl task failed: {}", e)));
+        results.push((url, result));
+    }
+    results
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio;
+    use crate::test_support::{http_response, request_path, spawn_delayed_mock_server, spawn_mock_server};
 
     #[tokio::test]
     async fn test_crawl_redirect_chain() {
-        let chain = crawl_redirect_chain("http://httpbin.org/redirect/1").await.unwrap();
-        assert!(chain.len() >= 2);
+        let result = crawl_redirect_chain("http://httpbin.org/redirect/1").await.unwrap();
+        assert!(result.chain.len() >= 2);
     }
 
     #[tokio::test]
@@ -168,10 +838,10 @@ mod tests {
             max_hops: 2,
             ..Default::default()
         };
-        let chain = crawl_redirect_chain_with_config("http://httpbin.org/redirect/3", &config)
+        let result = crawl_redirect_chain_with_config("http://httpbin.org/redirect/3", &config)
             .await
             .unwrap();
-        assert!(chain.len() <= 3); // Should stop at max_hops
+        assert!(result.chain.len() <= 3); // Should stop at max_hops
     }
 
     #[tokio::test]
@@ -193,4 +863,549 @@ mod tests {
         let result = crawl_redirect_chain_with_config("http://httpbin.org/redirect/1", &config).await;
         assert!(result.is_err());
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_host_override() {
+        // Only redirects when the original Host header survives the override
+        let addr = spawn_mock_server(|request| {
+            let host_ok = request.to_lowercase().contains("host: example.test");
+            if host_ok && request_path(request) == "/" {
+                http_response("302 Found", &[("Location", "/done")], "")
+            } else {
+                http_response("200 OK", &[], "ok")
+            }
+        }).await;
+
+        let config = CrawlerConfig {
+            host_overrides: vec![("example.test".to_string(), addr)],
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        let start_url = format!("http://example.test:{}/", addr.port());
+        let result = crawler.crawl(&start_url).await.unwrap();
+        assert_eq!(result.chain, vec![start_url.clone(), format!("{}done", start_url)]);
+        assert_eq!(result.termination, TerminationReason::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_denied_host() {
+        let addr = spawn_mock_server(move |request| {
+            if request.to_lowercase().contains("host: start.test") {
+                http_response("302 Found", &[("Location", "http://admin.internal/panel")], "")
+            } else {
+                http_response("200 OK", &[], "secret")
+            }
+        }).await;
+
+        let config = CrawlerConfig {
+            host_overrides: vec![("start.test".to_string(), addr)],
+            denylist: vec!["admin.internal".to_string()],
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        let err = crawler.crawl(&format!("http://start.test:{}/", addr.port())).await.unwrap_err();
+        let denied = err.downcast_ref::<DeniedUrl>().unwrap();
+        assert_eq!(denied.url, "http://admin.internal/panel");
+
+        assert!(crawler.crawl("http://admin.internal/").await.unwrap_err().is::<DeniedUrl>());
+    }
+
+    #[tokio::test]
+    async fn test_block_private_ips() {
+        let config = CrawlerConfig {
+            block_private_ips: true,
+            host_overrides: vec![("metadata.test".to_string(), "169.254.169.254:80".parse().unwrap())],
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        for url in ["http://169.254.169.254/latest/meta-data/", "http://metadata.test/", "http://[::1]/"] {
+            let err = crawler.crawl(url).await.unwrap_err();
+            let denied = err.downcast_ref::<DeniedUrl>().unwrap();
+            assert_eq!(denied.url, url);
+            assert!(denied.pattern.starts_with("private address"));
+        }
+
+        let err = crawler.crawl("http://unresolvable.invalid/").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeniedUrl>().unwrap().pattern, "unresolvable host");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_client_uses_checked_addresses() {
+        let addr = spawn_mock_server(|_| http_response("200 OK", &[], "")).await;
+        let crawler = Crawler::new(CrawlerConfig::default()).unwrap();
+        let url = format!("http://pinned.invalid:{}/", addr.port());
+        let client = crawler.pinned_client(&url, &[addr.ip()]).unwrap().unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), StatusCode::OK);
+
+        // Later hops to the same host and addresses reuse the client, also from derived crawlers
+        let key = ("pinned.invalid".to_string(), vec![SocketAddr::new(addr.ip(), addr.port())]);
+        assert!(crawler.pinned_clients.contains_key(&key));
+        crawler.with_max_hops(1).pinned_client(&format!("http://Pinned.invalid:{}/other", addr.port()), &[addr.ip()]).unwrap().unwrap();
+        crawler.pinned_clients.run_pending_tasks();
+        assert_eq!(crawler.pinned_clients.entry_count(), 1);
+
+        assert!(crawler.pinned_client("http://127.0.0.1/", &[addr.ip()]).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_post_body_redirect_semantics() {
+        let addr = spawn_mock_server(|request| {
+            let method = request.split_whitespace().next().unwrap_or("");
+            let has_body = request.to_ascii_lowercase().contains("content-length: 10");
+            match (method, request_path(request)) {
+                ("POST", "/login") if has_body => http_response("302 Found", &[("Location", "/welcome")], ""),
+                ("GET", "/welcome") => http_response("200 OK", &[], ""),
+                ("POST", "/see-other") => http_response("303 See Other", &[("Location", "/after")], ""),
+                ("GET", "/after") => http_response("200 OK", &[], ""),
+                ("POST", "/temporary") => http_response("307 Temporary Redirect", &[("Location", "/kept")], ""),
+                ("POST", "/kept") if has_body => http_response("200 OK", &[], ""),
+                _ => http_response("405 Method Not Allowed", &[], ""),
+            }
+        }).await;
+        let config = CrawlerConfig {
+            body: Some(b"user=alice".to_vec()),
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+
+        for (start, expected_end) in [("/login", "/welcome"), ("/see-other", "/after"), ("/temporary", "/kept")] {
+            let result = crawler.crawl(&format!("http://{}{}", addr, start)).await.unwrap();
+            assert_eq!(result.final_url(), Some(format!("http://{}{}", addr, expected_end).as_str()));
+            assert_eq!(result.hops.last().unwrap().status, Some(200), "{}", start);
+        }
+    }
+
+    #[test]
+    fn test_hop_delay_jitter() {
+        let base = Duration::from_millis(1000);
+        assert_eq!(hop_delay(base, None), base);
+        assert_eq!(hop_delay(base, Some(Duration::ZERO)), base);
+
+        let jitter = Duration::from_millis(250);
+        let delays: Vec<Duration> = (0..200).map(|_| hop_delay(base, Some(jitter))).collect();
+        assert!(delays.iter().all(|d| *d >= base - jitter && *d <= base + jitter));
+        // Not constant
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        // A jitter larger than the delay never goes negative
+        assert!((0..200).all(|_| hop_delay(Duration::from_millis(10), Some(jitter)) <= Duration::from_millis(260)));
+    }
+
+    #[test]
+    fn test_is_private_ip() {
+        for ip in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+                   "0.1.2.3", "224.0.0.1", "239.255.255.250", "255.255.255.255",
+                   "::", "::1", "fd00::1", "fe80::1", "ff02::1", "::ffff:10.0.0.1", "::127.0.0.1",
+                   "64:ff9b::a9fe:a9fe", "64:ff9b::10.0.0.1", "2002:a9fe:a9fe::1", "2002:c0a8:101::"] {
+            assert!(is_private_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "8.8.8.8", "100.128.0.1", "2606:4700::1111", "::ffff:8.8.8.8",
+                   "64:ff9b::808:808", "2002:808:808::1"] {
+            assert!(!is_private_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_exceeded() {
+        // Every hop takes 200ms and redirects to the next numbered path forever
+        let addr = spawn_delayed_mock_server(Duration::from_millis(200), |request| {
+            let current: u32 = request_path(request).trim_start_matches('/').parse().unwrap_or(0);
+            http_response("302 Found", &[("Location", &format!("/{}", current + 1))], "")
+        }).await;
+
+        let config = CrawlerConfig {
+            max_hops: 50,
+            rate_limit_delay: Duration::ZERO,
+            max_total_duration: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        let started = Instant::now();
+        let result = crawler.crawl(&format!("http://{}/0", addr)).await.unwrap();
+
+        assert_eq!(result.termination, TerminationReason::TimeBudgetExceeded);
+        assert!(result.chain.len() < 10);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_head_enumeration_falls_back_to_get() {
+        let addr = spawn_mock_server(|request| {
+            let is_head = request.starts_with("HEAD");
+            match (request_path(request), is_head) {
+                ("/", true) => http_response("405 Method Not Allowed", &[], ""),
+                ("/", false) => http_response("302 Found", &[("Location", "/next")], ""),
+                ("/next", true) => http_response("302 Found", &[("Location", "/last")], ""),
+                _ => http_response("200 OK", &[], ""),
+            }
+        }).await;
+
+        let config = CrawlerConfig {
+            use_head_requests: true,
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        let result = crawler.crawl(&format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(result.chain.len(), 3);
+        assert_eq!(result.final_url(), Some(format!("http://{}/last", addr).as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_loop_detected() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/a" => http_response("302 Found", &[("Location", "/a/")], ""),
+            _ => http_response("302 Found", &[("Location", "/a")], ""),
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/a", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.termination, TerminationReason::RedirectLoop);
+        assert_eq!(result.chain.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_respelled_loop_detected() {
+        // /a -> /a/ -> /a// -> ... never repeats a URL exactly
+        let addr = spawn_mock_server(|request| {
+            let location = format!("{}/", request_path(request));
+            http_response("302 Found", &[("Location", location.as_str())], "")
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/a", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.termination, TerminationReason::RedirectLoop);
+        assert_eq!(result.chain, vec![format!("http://{}/a", addr), format!("http://{}/a/", addr)]);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_canonicalization_followed() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/docs" => http_response("301 Moved Permanently", &[("Location", "/docs/")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/docs", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.termination, TerminationReason::Completed);
+        assert_eq!(result.final_url(), Some(format!("http://{}/docs/", addr).as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_crawl_multiple_with_configs() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/1" => http_response("302 Found", &[("Location", "/2")], ""),
+            "/2" => http_response("302 Found", &[("Location", "/3")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let url = format!("http://{}/1", addr);
+        let config = |max_hops| CrawlerConfig {
+            max_hops,
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let items = vec![
+            (url.clone(), config(1)),
+            ("ftp://example.com/".to_string(), config(5)),
+            (url.clone(), config(5)),
+        ];
+        let results = crawl_multiple_with_configs(&items, 2).await;
+        let urls: Vec<&str> = results.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, vec![url.as_str(), "ftp://example.com/", url.as_str()]);
+        assert_eq!(results[0].1.as_ref().unwrap().termination, TerminationReason::MaxHops);
+        assert!(results[1].1.is_err());
+        let full = results[2].1.as_ref().unwrap();
+        assert_eq!(full.termination, TerminationReason::Completed);
+        assert_eq!(full.chain.len(), 3);
+
+        let shared = crawl_multiple_urls(&[url.clone(), "ftp://example.com/".to_string()], &config(5), 1).await;
+        assert_eq!(shared[0].0, url);
+        assert_eq!(shared[0].1.as_ref().unwrap().chain.len(), 3);
+        assert_eq!(shared[1].0, "ftp://example.com/");
+        assert!(shared[1].1.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_per_host() {
+        let delay = Duration::from_millis(100);
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let times = served.clone();
+        let addr = spawn_delayed_mock_server(delay, move |_| {
+            times.lock().unwrap().push(Instant::now());
+            http_response("200 OK", &[], "")
+        }).await;
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            max_connections_per_host: Some(1),
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+
+        let urls: Vec<String> = ["a", "b", "c"].iter().map(|path| format!("http://{}/{}", addr, path)).collect();
+        let (a, b, c) = tokio::join!(crawler.crawl(&urls[0]), crawler.crawl(&urls[1]), crawler.crawl(&urls[2]));
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+
+        // Each request only went out once the previous response was in
+        let times = served.lock().unwrap().clone();
+        assert_eq!(times.len(), 3);
+        assert!(times.windows(2).all(|pair| pair[1].duration_since(pair[0]) >= delay - Duration::from_millis(10)));
+    }
+
+    #[tokio::test]
+    async fn test_host_header_stays_on_start_origin() {
+        let has_host = |request: &str| request.to_ascii_lowercase().contains("\r\nhost: example.test\r\n");
+        // Answers 400 if the header leaked to this other origin
+        let other = spawn_mock_server(move |request| match has_host(request) {
+            true => http_response("400 Bad Request", &[], ""),
+            false => http_response("200 OK", &[], ""),
+        }).await;
+        let addr = spawn_mock_server(move |request| match (has_host(request), request_path(request)) {
+            (true, "/") => http_response("302 Found", &[("Location", "/next")], ""),
+            (true, "/next") => http_response("302 Found", &[("Location", &format!("http://{}/", other))], ""),
+            _ => http_response("421 Misdirected Request", &[], ""),
+        }).await;
+
+        let config = CrawlerConfig { rate_limit_delay: Duration::ZERO, ..Default::default() };
+        let crawler = Crawler::new(config).unwrap().with_host_header("example.test");
+        let result = crawler.crawl(&format!("http://{}/", addr)).await.unwrap();
+        let statuses: Vec<Option<u16>> = result.hops.iter().map(|hop| hop.status).collect();
+        assert_eq!(statuses, vec![Some(302), Some(302), Some(200)]);
+    }
+
+    #[tokio::test]
+    async fn test_capture_bodies() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("302 Found", &[("Location", "/landing")], "moved"),
+            _ => http_response("200 OK", &[], "<html>hello</html>"),
+        }).await;
+        let url = format!("http://{}/", addr);
+
+        let config = CrawlerConfig { rate_limit_delay: Duration::ZERO, ..Default::default() };
+        let result = Crawler::new(config).unwrap().crawl(&url).await.unwrap();
+        assert!(result.hops.iter().all(|hop| hop.body.is_none()));
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            capture_bodies: true,
+            max_response_bytes: 10,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap().crawl(&url).await.unwrap();
+        let bodies: Vec<Option<&str>> = result.hops.iter().map(|hop| hop.body.as_deref()).collect();
+        assert_eq!(bodies, vec![Some("moved"), Some("<html>hell")]);
+        assert!(!result.bodies_truncated);
+
+        // One budget for the whole crawl: the second body only gets what's left
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            capture_bodies: true,
+            max_captured_body_bytes: 8,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap().crawl(&url).await.unwrap();
+        let bodies: Vec<Option<&str>> = result.hops.iter().map(|hop| hop.body.as_deref()).collect();
+        assert_eq!(bodies, vec![Some("moved"), Some("<ht")]);
+        assert!(result.bodies_truncated);
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            capture_bodies: true,
+            max_captured_body_bytes: 5,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap().crawl(&url).await.unwrap();
+        let bodies: Vec<Option<&str>> = result.hops.iter().map(|hop| hop.body.as_deref()).collect();
+        assert_eq!(bodies, vec![Some("moved"), None]);
+        assert!(result.bodies_truncated);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_transferred() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("302 Found", &[("Location", "/landing")], "moved"),
+            _ => http_response("200 OK", &[], "<html>hello</html>"),
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.bytes_transferred, ("moved".len() + "<html>hello</html>".len()) as u64);
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_max_hops() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/1" => http_response("302 Found", &[("Location", "/2")], ""),
+            "/2" => http_response("302 Found", &[("Location", "/3")], ""),
+            "/3" => http_response("302 Found", &[("Location", "/4")], ""),
+            "/4" => http_response("302 Found", &[("Location", "/1")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let url = |path: &str| format!("http://{}{}", addr, path);
+
+        let config = CrawlerConfig {
+            max_hops: 2,
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let crawler = Crawler::new(config).unwrap();
+        let first = crawler.crawl(&url("/1")).await.unwrap();
+        assert_eq!(first.termination, TerminationReason::MaxHops);
+        assert_eq!(first.chain, vec![url("/1"), url("/2"), url("/3")]);
+        assert_eq!(first.next_url, Some(url("/4")));
+
+        // The next page picks up at /4 and spots /4 -> /1 as a loop
+        let second = crawler.resume(&first).await.unwrap();
+        assert_eq!(second.chain, vec![url("/4")]);
+        assert_eq!(second.termination, TerminationReason::RedirectLoop);
+        assert_eq!(second.visited_urls.len(), 4);
+        assert!(second.next_url.is_none());
+        assert!(crawler.resume(&second).await.is_err());
+    }
+
+    #[test]
+    fn test_loop_key() {
+        assert_eq!(loop_key("HTTP://Example.COM:80/a/#top"), loop_key("http://example.com/a"));
+        assert_eq!(loop_key("https://example.com/"), loop_key("https://example.com"));
+        assert_ne!(loop_key("https://example.com/a?x=1"), loop_key("https://example.com/a?x=2"));
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        let base = "https://example.com/a/b?x=1";
+        // Scheme-relative: a different host, not a path on the current one
+        assert_eq!(resolve_location(base, "//evil.com/").unwrap(), "https://evil.com/");
+        assert_eq!(resolve_location("http://example.com/", "//evil.com/x").unwrap(), "http://evil.com/x");
+        assert_eq!(resolve_location(base, "/next#frag").unwrap(), "https://example.com/next#frag");
+        assert_eq!(resolve_location(base, "c").unwrap(), "https://example.com/a/c");
+        assert_eq!(resolve_location(base, "#top").unwrap(), "https://example.com/a/b?x=1#top");
+        // Relative paths that merely start with "http" are still relative
+        assert_eq!(resolve_location(base, "httpdocs/index").unwrap(), "https://example.com/a/httpdocs/index");
+        assert_eq!(resolve_location(base, " HTTPS://Other.example/ ").unwrap(), "https://other.example/");
+    }
+
+    #[tokio::test]
+    async fn test_final_content_type() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("302 Found", &[("Location", "/report.pdf")], ""),
+            _ => http_response("200 OK", &[("Content-Type", "application/pdf; qs=0.9")], "%PDF-1.4"),
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.final_content_type.as_deref(), Some("application/pdf"));
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_content_encoding() {
+        // Redirects only if identity encoding was requested, then answers with
+        // a gzip-declared but plain-text body
+        let addr = spawn_mock_server(|request| {
+            let identity = request.to_lowercase().contains("accept-encoding: identity");
+            match request_path(request) {
+                "/" if identity => http_response("302 Found", &[("Location", "/plain")], ""),
+                "/" => http_response("400 Bad Request", &[], ""),
+                _ => http_response("200 OK", &[("Content-Encoding", "gzip")], "<html>not gzip</html>"),
+            }
+        }).await;
+
+        let config = CrawlerConfig {
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://{}/", addr))
+            .await
+            .unwrap();
+        assert_eq!(result.chain.len(), 2);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(unexpected_content_encoding(&headers), None);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+        assert_eq!(unexpected_content_encoding(&headers), None);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        assert_eq!(unexpected_content_encoding(&headers).as_deref(), Some("gzip"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_hop_ips() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("301 Moved Permanently", &[("Location", "/landing")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+
+        let config = CrawlerConfig {
+            resolve_hop_ips: true,
+            host_overrides: vec![("pinned.test".to_string(), addr)],
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap()
+            .crawl(&format!("http://pinned.test:{}/", addr.port()))
+            .await
+            .unwrap();
+
+        let statuses: Vec<Option<u16>> = result.hops.iter().map(|hop| hop.status).collect();
+        assert_eq!(statuses, vec![Some(301), Some(200)]);
+        for hop in &result.hops {
+            assert_eq!(hop.resolved_ips, vec![addr.ip()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_followed_statuses() {
+        let addr = spawn_mock_server(|request| match request_path(request) {
+            "/" => http_response("301 Moved Permanently", &[("Location", "/permanent")], ""),
+            "/permanent" => http_response("308 Permanent Redirect", &[("Location", "/end")], ""),
+            _ => http_response("200 OK", &[], ""),
+        }).await;
+        let start = format!("http://{}/", addr);
+
+        let default_config = CrawlerConfig { rate_limit_delay: Duration::ZERO, ..Default::default() };
+        let result = Crawler::new(default_config).unwrap().crawl(&start).await.unwrap();
+        assert_eq!(result.chain.len(), 3);
+
+        let config = CrawlerConfig {
+            followed_statuses: [301, 302].into_iter().collect(),
+            rate_limit_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let result = Crawler::new(config).unwrap().crawl(&start).await.unwrap();
+        assert_eq!(result.termination, TerminationReason::Completed);
+        assert_eq!(result.final_url(), Some(format!("http://{}/permanent", addr).as_str()));
+        assert_eq!(result.hops.last().unwrap().status, Some(308));
+    }
+}
@@ -0,0 +1,90 @@
+use log::debug;
+use unicode_security::confusable_detection::skeleton;
+
+/// Names within this many edits of a brand are reported as lookalikes.
+const MAX_LOOKALIKE_DISTANCE: usize = 1;
+/// Brands with shorter names only match exactly, since one edit away
+/// from a short name is mostly unrelated domains.
+const MIN_FUZZY_BRAND_LENGTH: usize = 6;
+
+/// Domains that look like one of `brands` without being it (or one of its
+/// subdomains): homographs such as `pаypal.com` with a Cyrillic `а`,
+/// digit/letter swaps such as `paypa1.com`, and single-character typos such
+/// as `paypal.co`. Internationalized domains are compared in their Unicode
+/// form, using the UTS #39 confusable skeleton.
+pub fn suspected_lookalikes<'a>(domains: impl IntoIterator<Item = &'a str>, brands: &[String]) -> Vec<String> {
+    let brands: Vec<(String, String)> = brands.iter()
+        .map(|brand| brand.trim().trim_end_matches('.').to_lowercase())
+        .filter(|brand| !brand.is_empty())
+        .map(|brand| {
+            let skeleton = domain_skeleton(&brand);
+            (brand, skeleton)
+        })
+        .collect();
+
+    let mut lookalikes: Vec<String> = domains.into_iter()
+        .map(|domain| domain.trim_end_matches('.').to_lowercase())
+        .filter(|domain| brands.iter().any(|(brand, brand_skeleton)| is_lookalike(domain, brand, brand_skeleton)))
+        .collect();
+    lookalikes.sort();
+    lookalikes.dedup();
+    lookalikes
+}
+
+fn is_lookalike(domain: &str, brand: &str, brand_skeleton: &str) -> bool {
+    if domain == brand || domain.ends_with(&format!(".{}", brand)) {
+        return false;
+    }
+    let unicode = unicode_domain(domain);
+    let unicode = unicode.strip_prefix("www.").unwrap_or(&unicode);
+    let domain_skeleton = domain_skeleton(unicode);
+    // Skeletons expand some letters ("m" becomes "rn"), so typos are measured
+    // on the plain names as well
+    let matched = domain_skeleton == brand_skeleton
+        || (brand.chars().count() >= MIN_FUZZY_BRAND_LENGTH
+            && (strsim::levenshtein(&domain_skeleton, brand_skeleton) <= MAX_LOOKALIKE_DISTANCE
+                || strsim::levenshtein(unicode, brand) <= MAX_LOOKALIKE_DISTANCE));
+    if matched {
+        debug!("{} looks like {}", domain, brand);
+    }
+    matched
+}
+
+/// Punycode (`xn--`) labels decoded to Unicode; undecodable input is kept.
+fn unicode_domain(domain: &str) -> String {
+    let (unicode, result) = idna::domain_to_unicode(domain);
+    if result.is_ok() { unicode } else { domain.to_string() }
+}
+
+fn domain_skeleton(domain: &str) -> String {
+    skeleton(domain).flat_map(char::to_lowercase).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brands() -> Vec<String> {
+        vec!["paypal.com".to_string(), "ing.nl".to_string()]
+    }
+
+    #[test]
+    fn test_lookalikes_detected() {
+        let domains = [
+            "xn--pypal-4ve.com", // Cyrillic "а"
+            "paypa1.com",
+            "paypal.co",
+            "www.paypai.com",
+            "lng.nl",
+        ];
+        let found = suspected_lookalikes(domains, &brands());
+        assert_eq!(found, vec!["lng.nl", "paypa1.com", "paypal.co", "www.paypai.com", "xn--pypal-4ve.com"]);
+    }
+
+    #[test]
+    fn test_brand_and_unrelated_domains_ignored() {
+        let domains = ["paypal.com", "www.paypal.com", "PayPal.com.", "example.com", "ingnl.org", "big.nl"];
+        assert!(suspected_lookalikes(domains, &brands()).is_empty());
+        assert!(suspected_lookalikes(["paypa1.com"], &[]).is_empty());
+    }
+}
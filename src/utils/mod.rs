@@ -1,6 +1,9 @@
 pub mod logger;
 pub mod anonymizer;
 
+use anyhow::{Context, Result};
+use url::Url;
+
 pub fn url_to_snake_case(url: &str) -> String {
     let mut s = url.to_lowercase();
     s = s.replace("https", "");
@@ -11,4 +14,25 @@ pub fn url_to_snake_case(url: &str) -> String {
         s = s.replace("__", "_");
     }
     s.trim_matches('_').to_string()
-} 
\ No newline at end of file
+}
+
+/// Canonical spelling of `url`: lowercase scheme and host, no default port,
+/// `.`/`..` path segments resolved and an empty path written as `/`, so
+/// equivalent inputs share cache entries and file names.
+pub fn normalize_url(url: &str) -> Result<String> {
+    let parsed = Url::parse(url.trim()).with_context(|| format!("Invalid URL: {}", url))?;
+    Ok(parsed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("HTTP://Example.COM:80/a/../b").unwrap(), "http://example.com/b");
+        assert_eq!(normalize_url("https://example.com:443").unwrap(), "https://example.com/");
+        assert_eq!(normalize_url("https://example.com:8443/./x?q=A").unwrap(), "https://example.com:8443/x?q=A");
+        assert!(normalize_url("not a url").is_err());
+    }
+}